@@ -0,0 +1,37 @@
+/* C API of the fat-macho crate, built by the fat-macho-capi crate */
+#ifndef FAT_MACHO_H
+#define FAT_MACHO_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FatWriter FatWriter;
+
+/* Message of the last error on this thread, or NULL.
+ * Valid until the next failing call on this thread. */
+const char *fat_last_error(void);
+
+FatWriter *fat_writer_new(void);
+void fat_writer_free(FatWriter *writer);
+
+/* Add a thin or fat binary, copying `len` bytes from `data`.
+ * Returns 0 on success, -1 on failure. */
+int fat_writer_add(FatWriter *writer, const uint8_t *data, size_t len);
+
+/* Write the fat binary to `path`. Returns 0 on success, -1 on failure. */
+int fat_writer_write_file(const FatWriter *writer, const char *path);
+
+/* Find the thin binary of `arch` in a fat binary. Returns a pointer into
+ * `data` and stores its size in `out_len`, or NULL on failure. */
+const uint8_t *fat_reader_extract(const uint8_t *data, size_t len, const char *arch,
+                                  size_t *out_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FAT_MACHO_H */
@@ -0,0 +1,199 @@
+//! C API of the fat-macho crate, see `include/fat_macho.h`
+//!
+//! Functions returning `int` return 0 on success and -1 on failure, in which
+//! case `fat_last_error` describes the error.
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    ptr, slice,
+};
+
+use fat_macho_rs::{Error, FatReader, FatWriter};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: impl ToString) {
+    let message = CString::new(err.to_string().replace('\0', ""))
+        .unwrap_or_else(|_| CString::new("unknown error").unwrap());
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn to_status(result: Result<(), Error>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// Read a C string argument, recording an error if it's null or not UTF-8
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error(format!("{} is null", name));
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            set_last_error(format!("{} is not valid UTF-8", name));
+            None
+        }
+    }
+}
+
+/// Message of the last error on this thread, or null if there was none
+///
+/// The string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn fat_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Create a fat binary writer, to be freed with `fat_writer_free`
+#[no_mangle]
+pub extern "C" fn fat_writer_new() -> *mut FatWriter<'static> {
+    Box::into_raw(Box::new(FatWriter::new()))
+}
+
+/// Free a fat binary writer
+///
+/// # Safety
+///
+/// `writer` must be null or returned by `fat_writer_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn fat_writer_free(writer: *mut FatWriter<'static>) {
+    if !writer.is_null() {
+        drop(Box::from_raw(writer));
+    }
+}
+
+/// Add a thin or fat binary, copying `len` bytes from `data`
+///
+/// # Safety
+///
+/// `writer` must come from `fat_writer_new` and `data` must point to `len`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fat_writer_add(
+    writer: *mut FatWriter<'static>,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let Some(writer) = writer.as_mut() else {
+        set_last_error("writer is null");
+        return -1;
+    };
+    if data.is_null() {
+        set_last_error("data is null");
+        return -1;
+    }
+    to_status(writer.add(slice::from_raw_parts(data, len).to_vec()))
+}
+
+/// Write the fat binary to the file at `path`
+///
+/// # Safety
+///
+/// `writer` must come from `fat_writer_new` and `path` must be a
+/// nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fat_writer_write_file(
+    writer: *const FatWriter<'static>,
+    path: *const c_char,
+) -> c_int {
+    let Some(writer) = writer.as_ref() else {
+        set_last_error("writer is null");
+        return -1;
+    };
+    let Some(path) = str_arg(path, "path") else {
+        return -1;
+    };
+    to_status(writer.write_to_file(path).map(|_| ()))
+}
+
+/// Find the thin binary of `arch` in the `len` bytes of fat binary at `data`
+///
+/// Returns a pointer into `data` and stores the size of the thin binary in
+/// `out_len`, or returns null if `data` isn't a fat binary or doesn't
+/// contain `arch`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `arch` must be a
+/// nul-terminated string and `out_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn fat_reader_extract(
+    data: *const u8,
+    len: usize,
+    arch: *const c_char,
+    out_len: *mut usize,
+) -> *const u8 {
+    if data.is_null() || out_len.is_null() {
+        set_last_error("data or out_len is null");
+        return ptr::null();
+    }
+    let Some(arch) = str_arg(arch, "arch") else {
+        return ptr::null();
+    };
+    let reader = match FatReader::new(slice::from_raw_parts(data, len)) {
+        Ok(reader) => reader,
+        Err(err) => {
+            set_last_error(err);
+            return ptr::null();
+        }
+    };
+    match reader.extract(arch) {
+        Some(thin) => {
+            *out_len = thin.len();
+            thin.as_ptr()
+        }
+        None => {
+            set_last_error(Error::MissingArches(vec![arch.to_string()]));
+            ptr::null()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{CStr, CString},
+        fs, ptr, slice,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_capi() {
+        let f1 = fs::read("../tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("../tests/fixtures/thin_arm64").unwrap();
+        let path = CString::new("../tests/output/fat_capi").unwrap();
+        unsafe {
+            let writer = fat_writer_new();
+            assert_eq!(fat_writer_add(writer, f1.as_ptr(), f1.len()), 0);
+            assert_eq!(fat_writer_add(writer, f1.as_ptr(), f1.len()), -1);
+            let err = CStr::from_ptr(fat_last_error()).to_str().unwrap();
+            assert_eq!(err, "duplicated architecture x86_64");
+            assert_eq!(fat_writer_add(writer, f2.as_ptr(), f2.len()), 0);
+            assert_eq!(fat_writer_write_file(writer, path.as_ptr()), 0);
+            fat_writer_free(writer);
+        }
+
+        let fat = fs::read("../tests/output/fat_capi").unwrap();
+        let arch = CString::new("arm64").unwrap();
+        let mut len = 0;
+        unsafe {
+            let thin = fat_reader_extract(fat.as_ptr(), fat.len(), arch.as_ptr(), &mut len);
+            assert!(!thin.is_null());
+            assert_eq!(slice::from_raw_parts(thin, len), &f2[..]);
+
+            let arch = CString::new("i386").unwrap();
+            let thin = fat_reader_extract(fat.as_ptr(), fat.len(), arch.as_ptr(), &mut len);
+            assert_eq!(thin, ptr::null());
+        }
+    }
+}
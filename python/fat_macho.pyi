@@ -1,4 +1,4 @@
-from typing import Optional
+from typing import List, Optional
 
 
 class FatWriter:
@@ -8,3 +8,9 @@ class FatWriter:
     def exists(self, arch: str) -> bool: ...
     def write_to(self, path: str): ...
     def generate(self) -> bytes: ...
+
+
+class FatReader:
+    def __init__(self, data: bytes): ...
+    def archs(self) -> List[str]: ...
+    def extract(self, arch: str) -> Optional[bytes]: ...
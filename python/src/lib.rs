@@ -1,12 +1,13 @@
 use pyo3::exceptions::{PyOSError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::PyBytes;
 
 struct ErrorWrapper(fat_macho_rs::Error);
 
 /// Mach-O fat binary writer
 #[pyclass(module = "fat_macho")]
 struct FatWriter {
-    inner: fat_macho_rs::FatWriter,
+    inner: fat_macho_rs::FatWriter<'static>,
 }
 
 #[pymethods]
@@ -26,8 +27,10 @@ impl FatWriter {
     }
 
     /// Remove an architecture
-    fn remove(&mut self, arch: &str) -> Option<Vec<u8>> {
-        self.inner.remove(arch)
+    fn remove<'py>(&mut self, py: Python<'py>, arch: &str) -> Option<Bound<'py, PyBytes>> {
+        self.inner
+            .remove(arch)
+            .map(|data| PyBytes::new_bound(py, &data))
     }
 
     /// Check whether a certain architecture exists in this fat binary
@@ -42,10 +45,45 @@ impl FatWriter {
     }
 
     /// Generate Mach-O fat binary and return bytes
-    fn generate(&self) -> PyResult<Vec<u8>> {
+    fn generate<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
         let mut data = Vec::new();
         self.inner.write_to(&mut data).map_err(ErrorWrapper)?;
-        Ok(data)
+        Ok(PyBytes::new_bound(py, &data))
+    }
+}
+
+/// Mach-O fat binary reader
+#[pyclass(module = "fat_macho")]
+struct FatReader {
+    data: Vec<u8>,
+}
+
+impl FatReader {
+    fn reader(&self) -> fat_macho_rs::FatReader<'_> {
+        // Validated in `new`
+        fat_macho_rs::FatReader::new(&self.data).unwrap()
+    }
+}
+
+#[pymethods]
+impl FatReader {
+    /// Parse a Mach-O fat binary
+    #[new]
+    fn new(data: Vec<u8>) -> PyResult<Self> {
+        fat_macho_rs::FatReader::new(&data).map_err(ErrorWrapper)?;
+        Ok(Self { data })
+    }
+
+    /// Names of the architectures in this fat binary
+    fn archs(&self) -> Vec<String> {
+        self.reader().archs()
+    }
+
+    /// Extract thin binary by arch name
+    fn extract<'py>(&self, py: Python<'py>, arch: &str) -> Option<Bound<'py, PyBytes>> {
+        self.reader()
+            .extract(arch)
+            .map(|data| PyBytes::new_bound(py, data))
     }
 }
 
@@ -55,20 +93,15 @@ impl From<ErrorWrapper> for PyErr {
 
         match err.0 {
             Error::Io(e) => PyOSError::new_err(e.to_string()),
-            Error::Bitcode(e) => PyValueError::new_err(e.to_string()),
-            Error::InvalidMachO(e) => PyValueError::new_err(e.to_string()),
-            Error::DuplicatedArch(e) => PyValueError::new_err(e.to_string()),
-            Error::Goblin(e) => PyValueError::new_err(e.to_string()),
-            Error::NotFatBinary => {
-                PyValueError::new_err("input is not a Mach-O fat binary".to_string())
-            }
+            e => PyValueError::new_err(e.to_string()),
         }
     }
 }
 
 #[pymodule]
-fn fat_macho(_py: Python, m: &PyModule) -> PyResult<()> {
+fn fat_macho(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("__version__", env!("CARGO_PKG_VERSION"))?;
     m.add_class::<FatWriter>()?;
+    m.add_class::<FatReader>()?;
     Ok(())
 }
@@ -0,0 +1,29 @@
+import unittest
+from pathlib import Path
+
+from fat_macho import FatReader, FatWriter
+
+FIXTURES = Path(__file__).resolve().parents[2] / "tests" / "fixtures"
+
+
+class FatReaderTest(unittest.TestCase):
+    def test_archs(self):
+        reader = FatReader((FIXTURES / "simplefat").read_bytes())
+        self.assertEqual(reader.archs(), ["x86_64", "arm64"])
+
+    def test_extract(self):
+        thin = (FIXTURES / "thin_arm64").read_bytes()
+        writer = FatWriter()
+        writer.add(thin)
+        writer.add((FIXTURES / "thin_x86_64").read_bytes())
+        reader = FatReader(writer.generate())
+        self.assertEqual(reader.extract("arm64"), thin)
+        self.assertIsNone(reader.extract("i386"))
+
+    def test_not_fat(self):
+        with self.assertRaises(ValueError):
+            FatReader((FIXTURES / "thin_arm64").read_bytes())
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,100 @@
+use goblin::mach::cputype::{
+    get_arch_from_flag, get_arch_name_from_types, CpuSubType, CpuType, CPU_SUBTYPE_MASK,
+};
+
+/// Names that stand for every cpusubtype of their cputype, like lipo's family names
+const FAMILY_NAMES: &[&str] = &[
+    "i386", "x86_64", "arm", "arm64", "arm64_32", "ppc", "ppc64", "m68k", "hppa", "sparc", "m88k",
+    "i860",
+];
+
+/// Name of an architecture, ignoring the capability bits of the cpusubtype
+///
+/// arm64e slices carry their pointer authentication ABI version in the
+/// capability bits, which would otherwise make them nameless.
+pub(crate) fn arch_name(cpu_type: CpuType, cpu_subtype: CpuSubType) -> Option<&'static str> {
+    get_arch_name_from_types(cpu_type, cpu_subtype & !CPU_SUBTYPE_MASK)
+}
+
+/// Name of an architecture like `lipo -archs` prints it
+///
+/// Unknown architectures are described by their cputype and cpusubtype.
+pub(crate) fn display_arch_name(cpu_type: CpuType, cpu_subtype: CpuSubType) -> String {
+    match arch_name(cpu_type, cpu_subtype) {
+        Some(name) => name.to_string(),
+        None => format!(
+            "cputype {} cpusubtype {}",
+            cpu_type,
+            cpu_subtype & !CPU_SUBTYPE_MASK
+        ),
+    }
+}
+
+/// Check whether two architectures are the same, ignoring the capability bits of the cpusubtype
+pub(crate) fn same_arch(a: (CpuType, CpuSubType), b: (CpuType, CpuSubType)) -> bool {
+    a.0 == b.0 && a.1 & !CPU_SUBTYPE_MASK == b.1 & !CPU_SUBTYPE_MASK
+}
+
+/// Architectures selected by a family name such as `arm`, or a single architecture
+pub(crate) struct ArchFamily {
+    cpu_type: CpuType,
+    /// `None` for a family, matching every cpusubtype
+    cpu_subtype: Option<CpuSubType>,
+}
+
+impl ArchFamily {
+    /// Returns `None` if the name is unknown
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let (cpu_type, cpu_subtype) = get_arch_from_flag(name)?;
+        let cpu_subtype = (!FAMILY_NAMES.contains(&name)).then_some(cpu_subtype);
+        Some(Self {
+            cpu_type,
+            cpu_subtype,
+        })
+    }
+
+    pub(crate) fn contains(&self, cpu_type: CpuType, cpu_subtype: CpuSubType) -> bool {
+        match self.cpu_subtype {
+            Some(subtype) => same_arch((cpu_type, cpu_subtype), (self.cpu_type, subtype)),
+            None => cpu_type == self.cpu_type,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::mach::cputype::{
+        CPU_SUBTYPE_ARM64_E, CPU_SUBTYPE_ARM_V7, CPU_SUBTYPE_ARM_V7S, CPU_TYPE_ARM, CPU_TYPE_ARM64,
+    };
+
+    use super::{arch_name, display_arch_name, same_arch, ArchFamily};
+
+    #[test]
+    fn test_arch_name_ignores_capabilities() {
+        let ptrauth = CPU_SUBTYPE_ARM64_E | 0x8000_0000;
+        assert_eq!(arch_name(CPU_TYPE_ARM64, ptrauth), Some("arm64e"));
+        assert!(same_arch(
+            (CPU_TYPE_ARM64, ptrauth),
+            (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E)
+        ));
+        assert!(!same_arch((CPU_TYPE_ARM64, ptrauth), (CPU_TYPE_ARM64, 0)));
+        assert_eq!(display_arch_name(CPU_TYPE_ARM64, ptrauth), "arm64e");
+        assert_eq!(display_arch_name(42, 3), "cputype 42 cpusubtype 3");
+    }
+
+    #[test]
+    fn test_arch_family() {
+        let arm = ArchFamily::from_name("arm").unwrap();
+        assert!(arm.contains(CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7));
+        assert!(arm.contains(CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S));
+        assert!(!arm.contains(CPU_TYPE_ARM64, 0));
+
+        let armv7 = ArchFamily::from_name("armv7").unwrap();
+        assert!(armv7.contains(CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7));
+        assert!(!armv7.contains(CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S));
+
+        let arm64 = ArchFamily::from_name("arm64").unwrap();
+        assert!(arm64.contains(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E | 0x8000_0000));
+        assert!(ArchFamily::from_name("armv42").is_none());
+    }
+}
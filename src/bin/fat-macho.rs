@@ -0,0 +1,202 @@
+//! A `lipo`-like command line tool built on `FatReader` and `FatWriter`
+use std::{env, error::Error, fs, path::Path, process};
+
+use fat_macho::{FatReader, FatWriter};
+
+const USAGE: &str = "\
+usage: fat-macho <command> [arguments]
+
+commands:
+    create <input>... -output <output>          create a fat binary from the inputs
+    info <input>...                             describe the architectures of each input
+    archs <input>                               print the architecture names of the input
+    extract <input> <arch>... -output <output>  create a fat binary with only these architectures
+    remove <input> <arch>... -output <output>   create a fat binary without these architectures
+    thin <input> <arch> -output <output>        write the thin binary of one architecture
+    verify <input> <arch>...                    exit with an error unless all architectures are present";
+
+type CliResult<T = ()> = Result<T, Box<dyn Error>>;
+
+struct Args {
+    command: String,
+    positional: Vec<String>,
+    output: Option<String>,
+}
+
+impl Args {
+    fn parse() -> CliResult<Self> {
+        let mut args = env::args().skip(1);
+        let command = args.next().ok_or(USAGE)?;
+        let mut positional = Vec::new();
+        let mut output = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-output" | "-o" => {
+                    output = Some(args.next().ok_or("-output requires a path")?);
+                }
+                _ => positional.push(arg),
+            }
+        }
+        Ok(Self {
+            command,
+            positional,
+            output,
+        })
+    }
+
+    fn output(&self) -> CliResult<&str> {
+        self.output
+            .as_deref()
+            .ok_or_else(|| format!("{} requires -output", self.command).into())
+    }
+
+    /// Split the positional arguments into the input and at least one arch
+    fn input_and_arches(&self) -> CliResult<(&str, &[String])> {
+        match self.positional.split_first() {
+            Some((input, arches)) if !arches.is_empty() => Ok((input, arches)),
+            _ => Err(format!("{} requires an input and an architecture", self.command).into()),
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("fat-macho: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> CliResult {
+    let args = Args::parse()?;
+    match args.command.as_str() {
+        "create" => create(&args),
+        "info" => info(&args),
+        "archs" => archs(&args),
+        "extract" => extract(&args),
+        "remove" => remove(&args),
+        "thin" => thin(&args),
+        "verify" => verify(&args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        command => Err(format!("unknown command {}\n\n{}", command, USAGE).into()),
+    }
+}
+
+/// Read an input as a fat binary, wrapping a thin input in one
+fn read_fat(input: &str) -> CliResult<Vec<u8>> {
+    let buf = fs::read(input)?;
+    match FatReader::new(&buf).map(drop) {
+        Err(fat_macho::Error::NotFatBinary) => {
+            let mut fat = FatWriter::new();
+            fat.add(buf)?;
+            let mut out = Vec::new();
+            fat.write_to(&mut out)?;
+            Ok(out)
+        }
+        result => Ok(result.map(|()| buf)?),
+    }
+}
+
+fn create(args: &Args) -> CliResult {
+    if args.positional.is_empty() {
+        return Err("create requires at least one input".into());
+    }
+    let mut fat = FatWriter::new();
+    for input in &args.positional {
+        fat.add_path(input)?;
+    }
+    fat.write_to_file(args.output()?)?;
+    Ok(())
+}
+
+fn info(args: &Args) -> CliResult {
+    if args.positional.is_empty() {
+        return Err("info requires at least one input".into());
+    }
+    for input in &args.positional {
+        let buf = fs::read(input)?;
+        match FatReader::new(&buf) {
+            Ok(reader) => println!(
+                "Architectures in the fat file: {} are: {}",
+                input,
+                reader.archs().join(" ")
+            ),
+            Err(fat_macho::Error::NotFatBinary) => {
+                let mut thin = FatWriter::new();
+                thin.add_borrowed(&buf)?;
+                println!(
+                    "Non-fat file: {} is architecture: {}",
+                    input,
+                    thin.archs().join(" ")
+                );
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+fn archs(args: &Args) -> CliResult {
+    let input = match args.positional.as_slice() {
+        [input] => input,
+        _ => return Err("archs requires exactly one input".into()),
+    };
+    println!("{}", FatReader::new(&read_fat(input)?)?.archs().join(" "));
+    Ok(())
+}
+
+fn extract(args: &Args) -> CliResult {
+    let (input, arches) = args.input_and_arches()?;
+    let buf = read_fat(input)?;
+    let reader = FatReader::new(&buf)?;
+    reader.covers(&arches.iter().map(String::as_str).collect::<Vec<_>>())?;
+    let mut fat = FatWriter::new();
+    fat.add_borrowed(&buf)?;
+    // Remove by cpu type so that architectures without a name are removed too
+    for arch in reader.arches() {
+        let requested = arch
+            .arch_name
+            .is_some_and(|name| arches.iter().any(|arch| arch == name));
+        if !requested {
+            fat.remove_by_type(arch.cputype, arch.cpusubtype)?;
+        }
+    }
+    fat.write_to_file(args.output()?)?;
+    Ok(())
+}
+
+fn remove(args: &Args) -> CliResult {
+    let (input, arches) = args.input_and_arches()?;
+    let buf = fs::read(input)?;
+    let mut fat = FatWriter::new();
+    fat.add_borrowed(&buf)?;
+    for arch in arches {
+        if fat.try_remove(arch)?.is_none() {
+            return Err(format!("{} does not contain architecture {}", input, arch).into());
+        }
+    }
+    fat.write_to_file(args.output()?)?;
+    Ok(())
+}
+
+fn thin(args: &Args) -> CliResult {
+    let (input, arch) = match args.input_and_arches()? {
+        (input, [arch]) => (input, arch),
+        _ => return Err("thin requires exactly one architecture".into()),
+    };
+    let mut slice = Vec::new();
+    FatReader::new(&fs::read(input)?)?.thin(&[arch], &mut slice)?;
+    let output = Path::new(args.output()?);
+    fs::write(output, slice)?;
+    fs::set_permissions(output, fs::metadata(input)?.permissions())?;
+    Ok(())
+}
+
+fn verify(args: &Args) -> CliResult {
+    let (input, arches) = args.input_and_arches()?;
+    let arches: Vec<&str> = arches.iter().map(String::as_str).collect();
+    FatReader::new(&read_fat(input)?)?.covers(&arches)?;
+    Ok(())
+}
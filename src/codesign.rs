@@ -0,0 +1,528 @@
+//! Code signatures of thin Mach-O slices
+use goblin::mach::{
+    header::{MH_MAGIC, MH_MAGIC_64, SIZEOF_HEADER_32, SIZEOF_HEADER_64},
+    load_command::{LC_CODE_SIGNATURE, LC_SEGMENT, LC_SEGMENT_64},
+};
+
+use crate::error::Error;
+
+/// Byte order and width of a Mach-O header
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    le: bool,
+    is_64: bool,
+}
+
+impl Fields {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let magic = u32::from_le_bytes(buf.get(..4)?.try_into().ok()?);
+        match (magic, magic.swap_bytes()) {
+            (MH_MAGIC, _) => Some(Self {
+                le: true,
+                is_64: false,
+            }),
+            (MH_MAGIC_64, _) => Some(Self {
+                le: true,
+                is_64: true,
+            }),
+            (_, MH_MAGIC) => Some(Self {
+                le: false,
+                is_64: false,
+            }),
+            (_, MH_MAGIC_64) => Some(Self {
+                le: false,
+                is_64: true,
+            }),
+            _ => None,
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        if self.is_64 {
+            SIZEOF_HEADER_64
+        } else {
+            SIZEOF_HEADER_32
+        }
+    }
+
+    fn read32(&self, buf: &[u8], offset: usize) -> Option<u32> {
+        let bytes = buf.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Read a segment address or size, which is 64-bit only in LC_SEGMENT_64
+    fn read_word(&self, buf: &[u8], offset: usize) -> Option<u64> {
+        if !self.is_64 {
+            return self.read32(buf, offset).map(u64::from);
+        }
+        let bytes = buf.get(offset..offset.checked_add(8)?)?.try_into().ok()?;
+        Some(if self.le {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    fn write32(&self, buf: &mut [u8], offset: usize, value: u32) {
+        let bytes = if self.le {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        };
+        buf[offset..offset + 4].copy_from_slice(&bytes);
+    }
+
+    fn write_word(&self, buf: &mut [u8], offset: usize, value: u64) {
+        if self.is_64 {
+            let bytes = if self.le {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            };
+            buf[offset..offset + 8].copy_from_slice(&bytes);
+        } else {
+            self.write32(buf, offset, value as u32);
+        }
+    }
+}
+
+/// A load command and its offset in the slice
+#[derive(Debug, Clone, Copy)]
+struct LoadCommand {
+    offset: usize,
+    cmd: u32,
+    cmdsize: usize,
+}
+
+/// A parsed thin Mach-O header and its load commands
+#[derive(Debug)]
+struct MachHeader {
+    fields: Fields,
+    ncmds: u32,
+    sizeofcmds: usize,
+    commands: Vec<LoadCommand>,
+}
+
+impl MachHeader {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let fields = Fields::parse(buf)?;
+        let ncmds = fields.read32(buf, 16)?;
+        let sizeofcmds = fields.read32(buf, 20)? as usize;
+        let end = fields.header_len().checked_add(sizeofcmds)?;
+        let mut commands = Vec::new();
+        let mut offset = fields.header_len();
+        for _ in 0..ncmds {
+            let cmd = fields.read32(buf, offset)?;
+            let cmdsize = fields.read32(buf, offset + 4)? as usize;
+            if cmdsize == 0 || offset.checked_add(cmdsize)? > end.min(buf.len()) {
+                return None;
+            }
+            commands.push(LoadCommand {
+                offset,
+                cmd,
+                cmdsize,
+            });
+            offset += cmdsize;
+        }
+        Some(Self {
+            fields,
+            ncmds,
+            sizeofcmds,
+            commands,
+        })
+    }
+
+    fn commands_end(&self) -> usize {
+        self.fields.header_len() + self.sizeofcmds
+    }
+
+    fn code_signature(&self) -> Option<LoadCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.cmd == LC_CODE_SIGNATURE)
+            .copied()
+    }
+
+    fn segment(&self, buf: &[u8], name: &str) -> Option<Segment> {
+        self.commands
+            .iter()
+            .filter(|command| matches!(command.cmd, LC_SEGMENT | LC_SEGMENT_64))
+            .find(|command| {
+                buf.get(command.offset + 8..command.offset + 24)
+                    .is_some_and(|segname| {
+                        segname.split(|&b| b == 0).next() == Some(name.as_bytes())
+                    })
+            })
+            .and_then(|command| Segment::parse(self.fields, buf, command))
+    }
+
+    /// Offset of the first section contents, which the load commands must end before
+    #[cfg(feature = "codesign")]
+    fn first_section_offset(&self, buf: &[u8]) -> Option<usize> {
+        let fields = self.fields;
+        // (segment_command size, section size, offset of section offset, offset of nsects)
+        let (segment_size, section_size, offset_field, nsects_field) = if fields.is_64 {
+            (72, 80, 48, 64)
+        } else {
+            (56, 68, 40, 48)
+        };
+        let mut first = None;
+        for command in &self.commands {
+            if !matches!(command.cmd, LC_SEGMENT | LC_SEGMENT_64) {
+                continue;
+            }
+            let nsects = fields.read32(buf, command.offset + nsects_field)? as usize;
+            for index in 0..nsects {
+                let section = command.offset + segment_size + index * section_size;
+                let offset = fields.read32(buf, section + offset_field)? as usize;
+                // Zero-fill sections have no file contents
+                if offset != 0 && !first.is_some_and(|first| offset >= first) {
+                    first = Some(offset);
+                }
+            }
+        }
+        first
+    }
+}
+
+/// The file layout of a segment load command
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    offset: usize,
+    vmsize: u64,
+    fileoff: u64,
+    filesize: u64,
+}
+
+impl Segment {
+    fn parse(fields: Fields, buf: &[u8], command: &LoadCommand) -> Option<Self> {
+        let word = if fields.is_64 { 8 } else { 4 };
+        // Size of a segment_command or segment_command_64 without sections
+        if command.cmdsize < 24 + 4 * word + 16 {
+            return None;
+        }
+        let offset = command.offset;
+        Some(Self {
+            offset,
+            vmsize: fields.read_word(buf, offset + 24 + word)?,
+            fileoff: fields.read_word(buf, offset + 24 + 2 * word)?,
+            filesize: fields.read_word(buf, offset + 24 + 3 * word)?,
+        })
+    }
+
+    /// End of the segment contents in the file, `None` if it overflows
+    fn file_end(&self) -> Option<u64> {
+        self.fileoff.checked_add(self.filesize)
+    }
+
+    /// Returns `None` if the load command doesn't fit in `buf`
+    fn set_sizes(&self, fields: Fields, buf: &mut [u8], vmsize: u64, filesize: u64) -> Option<()> {
+        let word = if fields.is_64 { 8 } else { 4 };
+        if self.offset.checked_add(24 + 4 * word)? > buf.len() {
+            return None;
+        }
+        fields.write_word(buf, self.offset + 24 + word, vmsize);
+        fields.write_word(buf, self.offset + 24 + 3 * word, filesize);
+        Some(())
+    }
+}
+
+/// Check whether a thin Mach-O slice has an `LC_CODE_SIGNATURE` load command
+pub(crate) fn has_code_signature(buf: &[u8]) -> bool {
+    MachHeader::parse(buf).is_some_and(|header| header.code_signature().is_some())
+}
+
+/// Remove the `LC_CODE_SIGNATURE` load command and signature blob of a thin Mach-O slice
+///
+/// The blob is only dropped, and `__LINKEDIT` shrunk, when it's at the end
+/// of the slice as linkers and `codesign` put it. Returns `None` if the
+/// slice isn't signed.
+pub(crate) fn strip_code_signature(buf: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let Some(header) = MachHeader::parse(buf) else {
+        return Ok(None);
+    };
+    let Some(signature) = header.code_signature() else {
+        return Ok(None);
+    };
+    let fields = header.fields;
+    let end = header.commands_end();
+    let malformed = || Error::InvalidMachO("malformed LC_CODE_SIGNATURE".to_string());
+    let dataoff = fields
+        .read32(buf, signature.offset + 8)
+        .ok_or_else(malformed)? as usize;
+    let datasize = fields
+        .read32(buf, signature.offset + 12)
+        .ok_or_else(malformed)? as usize;
+    // The signature must follow the load commands, which must fit in the slice
+    if end > buf.len() || dataoff < end {
+        return Err(malformed());
+    }
+    let mut out = buf.to_vec();
+    if dataoff <= buf.len() && dataoff.saturating_add(datasize) >= buf.len() {
+        out.truncate(dataoff);
+        if let Some(linkedit) = header.segment(buf, "__LINKEDIT") {
+            if linkedit.file_end() == Some(buf.len() as u64) {
+                let filesize = (dataoff as u64).saturating_sub(linkedit.fileoff);
+                linkedit
+                    .set_sizes(fields, &mut out, linkedit.vmsize, filesize)
+                    .ok_or_else(malformed)?;
+            }
+        }
+    }
+    out.copy_within(signature.offset + signature.cmdsize..end, signature.offset);
+    out[end - signature.cmdsize..end].fill(0);
+    fields.write32(&mut out, 16, header.ncmds - 1);
+    fields.write32(&mut out, 20, (header.sizeofcmds - signature.cmdsize) as u32);
+    Ok(Some(out))
+}
+
+#[cfg(feature = "codesign")]
+pub(crate) use self::adhoc::ad_hoc_sign;
+
+#[cfg(feature = "codesign")]
+mod adhoc {
+    use goblin::mach::{header::MH_EXECUTE, load_command::LC_CODE_SIGNATURE};
+    use sha2::{Digest, Sha256};
+
+    use super::{strip_code_signature, MachHeader};
+    use crate::error::Error;
+
+    /// Size of a linkedit_data_command
+    const SIZEOF_LINKEDIT_DATA_COMMAND: usize = 16;
+    const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
+    const CSMAGIC_CODEDIRECTORY: u32 = 0xfade0c02;
+    const CSSLOT_CODEDIRECTORY: u32 = 0;
+    /// Code directory version with the exec segment fields
+    const CS_SUPPORTSEXECSEG: u32 = 0x20400;
+    const CS_ADHOC: u32 = 0x2;
+    const CS_LINKER_SIGNED: u32 = 0x20000;
+    const CS_HASHTYPE_SHA256: u8 = 2;
+    const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+    const PAGE_SIZE_BITS: u8 = 12;
+    const SIZEOF_CODE_DIRECTORY: usize = 88;
+    /// Size of a SuperBlob header with a single BlobIndex
+    const SIZEOF_SUPER_BLOB: usize = 20;
+
+    /// Replace the code signature of a thin Mach-O slice with an ad-hoc one
+    ///
+    /// The signature is a SHA-256 code directory without requirements or
+    /// entitlements, like the one the linker adds for arm64. Fails if
+    /// `__LINKEDIT` isn't at the end of the slice or there's no room left for
+    /// another load command.
+    pub(crate) fn ad_hoc_sign(buf: &[u8], identifier: &str) -> Result<Vec<u8>, Error> {
+        let invalid = |msg: &str| Error::InvalidMachO(msg.to_string());
+        let mut out = strip_code_signature(buf)?.unwrap_or_else(|| buf.to_vec());
+        let header = MachHeader::parse(&out).ok_or_else(|| invalid("input is not a macho file"))?;
+        let fields = header.fields;
+        let linkedit = header
+            .segment(&out, "__LINKEDIT")
+            .filter(|linkedit| linkedit.file_end() == Some(out.len() as u64))
+            .ok_or_else(|| invalid("__LINKEDIT is not at the end of the file"))?;
+        let (exec_seg_base, exec_seg_limit) = header
+            .segment(&out, "__TEXT")
+            .map_or((0, 0), |text| (text.fileoff, text.filesize));
+        let end = header.commands_end();
+        let room = header
+            .first_section_offset(&out)
+            .unwrap_or(linkedit.fileoff as usize);
+        if end + SIZEOF_LINKEDIT_DATA_COMMAND > room {
+            return Err(invalid("no room for LC_CODE_SIGNATURE"));
+        }
+
+        let code_limit = (out.len() + 15) & !15;
+        let code_limit_u32 = u32::try_from(code_limit)
+            .map_err(|_| invalid("file is too large for a code signature"))?;
+        out.resize(code_limit, 0);
+        let pages = (code_limit + (1 << PAGE_SIZE_BITS) - 1) >> PAGE_SIZE_BITS;
+        let ident_len = identifier.len() + 1;
+        let cd_len = SIZEOF_CODE_DIRECTORY + ident_len + pages * 32;
+        let sig_len = SIZEOF_SUPER_BLOB + cd_len;
+
+        fields.write32(&mut out, end, LC_CODE_SIGNATURE);
+        fields.write32(&mut out, end + 4, SIZEOF_LINKEDIT_DATA_COMMAND as u32);
+        fields.write32(&mut out, end + 8, code_limit_u32);
+        fields.write32(&mut out, end + 12, sig_len as u32);
+        fields.write32(&mut out, 16, header.ncmds + 1);
+        fields.write32(
+            &mut out,
+            20,
+            (header.sizeofcmds + SIZEOF_LINKEDIT_DATA_COMMAND) as u32,
+        );
+        let filesize = (code_limit + sig_len) as u64 - linkedit.fileoff;
+        // 16 KiB is a multiple of the page size of every cpu type
+        let vmsize = linkedit.vmsize.max((filesize + 0x3fff) & !0x3fff);
+        linkedit
+            .set_sizes(fields, &mut out, vmsize, filesize)
+            .ok_or_else(|| invalid("malformed __LINKEDIT"))?;
+
+        // Code signature blobs are big-endian
+        let mut sig = Vec::with_capacity(sig_len);
+        for field in [
+            CSMAGIC_EMBEDDED_SIGNATURE,
+            sig_len as u32,
+            1,
+            CSSLOT_CODEDIRECTORY,
+            SIZEOF_SUPER_BLOB as u32,
+            CSMAGIC_CODEDIRECTORY,
+            cd_len as u32,
+            CS_SUPPORTSEXECSEG,
+            CS_ADHOC | CS_LINKER_SIGNED,
+            (SIZEOF_CODE_DIRECTORY + ident_len) as u32, // hashOffset
+            SIZEOF_CODE_DIRECTORY as u32,               // identOffset
+            0,                                          // nSpecialSlots
+            pages as u32,
+            code_limit_u32,
+        ] {
+            sig.extend_from_slice(&field.to_be_bytes());
+        }
+        sig.extend_from_slice(&[32, CS_HASHTYPE_SHA256, 0, PAGE_SIZE_BITS]);
+        // spare2, scatterOffset, teamOffset and spare3
+        sig.extend_from_slice(&[0; 16]);
+        let exec_seg_flags = if fields.read32(&out, 12) == Some(MH_EXECUTE) {
+            CS_EXECSEG_MAIN_BINARY
+        } else {
+            0
+        };
+        // codeLimit64 is only used past 4 GiB
+        for field in [0, exec_seg_base, exec_seg_limit, exec_seg_flags] {
+            sig.extend_from_slice(&field.to_be_bytes());
+        }
+        sig.extend_from_slice(identifier.as_bytes());
+        sig.push(0);
+        for page in out.chunks(1 << PAGE_SIZE_BITS) {
+            sig.extend_from_slice(&Sha256::digest(page));
+        }
+        debug_assert_eq!(sig.len(), sig_len);
+        out.extend_from_slice(&sig);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{has_code_signature, strip_code_signature, MachHeader};
+    use crate::error::Error;
+    use crate::read::FatReader;
+
+    #[test]
+    fn test_strip_code_signature() {
+        let signed = fs::read("tests/fixtures/thin_arm64").unwrap();
+        assert!(has_code_signature(&signed));
+        let stripped = strip_code_signature(&signed).unwrap().unwrap();
+        assert!(!has_code_signature(&stripped));
+        assert_eq!(stripped.len(), 0x40a0);
+        assert_eq!(&stripped[0x2000..], &signed[0x2000..0x40a0]);
+        let macho = goblin::mach::MachO::parse(&stripped, 0).unwrap();
+        let linkedit = macho
+            .segments
+            .iter()
+            .find(|seg| seg.name().unwrap() == "__LINKEDIT")
+            .unwrap();
+        assert_eq!(linkedit.fileoff + linkedit.filesize, 0x40a0);
+
+        let unsigned = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert!(!has_code_signature(&unsigned));
+        assert_eq!(strip_code_signature(&unsigned).unwrap(), None);
+
+        // dataoff inside the load commands
+        let mut bad = signed.clone();
+        let header = MachHeader::parse(&signed).unwrap();
+        let offset = header.code_signature().unwrap().offset;
+        bad[offset + 8..offset + 12].fill(0);
+        assert!(matches!(
+            strip_code_signature(&bad),
+            Err(Error::InvalidMachO(_))
+        ));
+        // sizeofcmds past the end of the slice
+        let mut bad = signed.clone();
+        bad[20..24].copy_from_slice(&(signed.len() as u32).to_le_bytes());
+        assert!(matches!(
+            strip_code_signature(&bad),
+            Err(Error::InvalidMachO(_))
+        ));
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let signed: Vec<_> = reader
+            .arches()
+            .map(|arch| arch.has_code_signature)
+            .collect();
+        assert_eq!(signed, [false, false, true]);
+    }
+
+    /// A 64-bit slice with a `__LINKEDIT` segment command and a code signature
+    /// at the end, whose segment fields are patched in afterwards
+    fn signed_macho(segment_cmdsize: u32, signature: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let sizeofcmds = segment_cmdsize + 16;
+        for field in [0xfeedfacf, 0x0100000c, 0, 2, 2, sizeofcmds, 0, 0] {
+            buf.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        buf.extend_from_slice(&0x19u32.to_le_bytes());
+        buf.extend_from_slice(&segment_cmdsize.to_le_bytes());
+        buf.extend_from_slice(b"__LINKEDIT\0\0\0\0\0\0");
+        buf.resize(32 + segment_cmdsize as usize, 0);
+        let dataoff = buf.len() as u32 + 16;
+        for field in [0x1d, 16, dataoff, signature.len() as u32] {
+            buf.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        buf.extend_from_slice(signature);
+        buf
+    }
+
+    #[test]
+    fn test_strip_code_signature_malformed_segment() {
+        // cmdsize too small to hold the segment fields, which would be read
+        // from the signature and written past the stripped slice
+        let mut signature = [0; 16];
+        signature[8..].copy_from_slice(&88u64.to_le_bytes());
+        let buf = signed_macho(24, &signature);
+        assert_eq!(buf.len(), 88);
+        let stripped = strip_code_signature(&buf).unwrap().unwrap();
+        assert_eq!(stripped.len(), 72);
+        assert!(!has_code_signature(&stripped));
+
+        // fileoff + filesize overflows
+        let mut buf = signed_macho(72, &[0; 8]);
+        buf[72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+        buf[80..88].copy_from_slice(&2u64.to_le_bytes());
+        let stripped = strip_code_signature(&buf).unwrap().unwrap();
+        assert_eq!(stripped.len(), 120);
+        #[cfg(feature = "codesign")]
+        assert!(matches!(
+            super::ad_hoc_sign(&buf, "bad"),
+            Err(Error::InvalidMachO(_))
+        ));
+    }
+
+    #[cfg(feature = "codesign")]
+    #[test]
+    fn test_ad_hoc_sign() {
+        use super::ad_hoc_sign;
+
+        // Matches the signature ld64 adds
+        let signed = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let stripped = strip_code_signature(&signed).unwrap().unwrap();
+        assert_eq!(ad_hoc_sign(&stripped, "thin_arm64").unwrap(), signed);
+        assert_eq!(ad_hoc_sign(&signed, "thin_arm64").unwrap(), signed);
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let arm64 = reader.extract("arm64").unwrap();
+        assert_eq!(ad_hoc_sign(arm64, "hello.arm64").unwrap(), arm64);
+
+        let unsigned = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let resigned = ad_hoc_sign(&unsigned, "thin_x86_64").unwrap();
+        assert!(has_code_signature(&resigned));
+        assert!(goblin::mach::MachO::parse(&resigned, 0).is_ok());
+        let stripped = strip_code_signature(&resigned).unwrap().unwrap();
+        assert_eq!(stripped.len(), (unsigned.len() + 15) & !15);
+        assert_eq!(&stripped[0x1000..unsigned.len()], &unsigned[0x1000..]);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    fs::{self, File},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
+
+use crate::error::Error;
+use crate::stream::read_fat_arches;
+use crate::write::FatWriter;
+
+/// Edit a fat binary on disk
+///
+/// The fat binary is opened with [`FatWriter::add_path`] into a writer, which
+/// this derefs to, so architectures can be added, removed and replaced before
+/// calling [`FatEditor::save`]. Slices keep their order and alignment from the
+/// original file, and hidden arm64 slices stay hidden.
+#[derive(Debug)]
+pub struct FatEditor {
+    path: PathBuf,
+    writer: FatWriter<'static>,
+}
+
+impl FatEditor {
+    /// Load a fat binary from `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let hidden = read_fat_arches(&mut File::open(&path)?)?
+            .is_some_and(|arches| arches.iter().any(|arch| arch.hidden));
+        let mut writer = FatWriter::new();
+        writer.preserve_input_order(true);
+        writer.set_hide_arm64(hidden);
+        writer.add_path(&path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path)?.permissions().mode();
+            writer.set_file_mode(mode & 0o7777);
+        }
+        Ok(Self { path, writer })
+    }
+
+    /// Path of the fat binary being edited
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the edited fat binary back to its path, returning its size
+    ///
+    /// Slices are still read from the original file, so it is always
+    /// replaced by renaming a temporary file over it, even with
+    /// [`FatWriter::set_write_in_place`].
+    pub fn save(&self) -> Result<u64, Error> {
+        self.writer.replace_file(&self.path)
+    }
+
+    /// Stop editing, returning the underlying writer
+    pub fn into_writer(self) -> FatWriter<'static> {
+        self.writer
+    }
+}
+
+impl Deref for FatEditor {
+    type Target = FatWriter<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.writer
+    }
+}
+
+impl DerefMut for FatEditor {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::FatEditor;
+    use crate::read::FatReader;
+
+    #[test]
+    fn test_fat_editor() {
+        let path = "tests/output/fat_editor";
+        fs::copy("tests/fixtures/hellofat", path).unwrap();
+        let original = fs::read(path).unwrap();
+
+        let mut editor = FatEditor::open(path).unwrap();
+        assert!(editor.exists("x86_64h"));
+        editor.save().unwrap();
+        assert_eq!(fs::read(path).unwrap(), original);
+
+        editor.remove("x86_64h").unwrap();
+        editor.save().unwrap();
+        let buf = fs::read(path).unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let original = FatReader::new(&original).unwrap();
+        assert_eq!(reader.arches().count(), 2);
+        assert_eq!(reader.extract("x86_64h"), None);
+        assert_eq!(reader.extract("arm64"), original.extract("arm64"));
+    }
+
+    #[test]
+    fn test_fat_editor_hidden_arm64() {
+        use goblin::mach::cputype::CPU_TYPE_ARM;
+
+        use crate::test_support::minimal_macho;
+        use crate::{FatWriter, FatWriterOptions};
+
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().hide_arm64(true));
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        fat.add(minimal_macho(CPU_TYPE_ARM, 9)).unwrap();
+        let path = "tests/output/fat_editor_hidden_arm64";
+        fat.write_to_file(path).unwrap();
+        let original = fs::read(path).unwrap();
+
+        let mut editor = FatEditor::open(path).unwrap();
+        editor.set_write_in_place(true);
+        editor.save().unwrap();
+        assert_eq!(fs::read(path).unwrap(), original);
+        let buf = fs::read(path).unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let hidden: Vec<_> = reader.arches().map(|arch| arch.hidden).collect();
+        assert_eq!(hidden, [false, true]);
+    }
+}
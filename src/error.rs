@@ -1,12 +1,46 @@
 use std::{error, fmt, io};
 
+use goblin::mach::header::filetype_to_str;
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Io(io::Error),
     Goblin(goblin::error::Error),
     NotFatBinary,
+    /// The input ends before the fat header does
+    Truncated {
+        needed: u64,
+        len: u64,
+    },
+    /// `nfat_arch` declares more fat_arch entries than fit in the input
+    TooManyArches {
+        count: u32,
+        max: u32,
+    },
+    /// A slice extends past the end of the input
+    SliceOutOfBounds {
+        index: usize,
+        offset: u64,
+        size: u64,
+        len: u64,
+    },
+    /// A fat_arch entry declares an alignment above 2^15
+    BadAlignment {
+        index: usize,
+        offset: u64,
+        align: u32,
+    },
+    NoArches,
     InvalidMachO(String),
     DuplicatedArch(String),
+    MissingArches(Vec<String>),
+    Fat32Overflow(String),
+    ArchMismatch {
+        expected: String,
+        found: String,
+    },
+    MixedFiletypes(Vec<(String, u32)>),
     #[cfg(feature = "bitcode")]
     Bitcode(llvm_bitcode::read::Error),
 }
@@ -17,8 +51,54 @@ impl fmt::Display for Error {
             Error::Io(err) => err.fmt(f),
             Error::Goblin(err) => err.fmt(f),
             Error::NotFatBinary => write!(f, "input is not a valid Mach-O fat binary"),
+            Error::Truncated { needed, len } => write!(
+                f,
+                "input is truncated: expected at least {} bytes but found {}",
+                needed, len
+            ),
+            Error::TooManyArches { count, max } => write!(
+                f,
+                "fat header declares {} architectures but only {} fit in the input",
+                count, max
+            ),
+            Error::SliceOutOfBounds {
+                index,
+                offset,
+                size,
+                len,
+            } => write!(
+                f,
+                "slice {} at offset {:#x} with size {:#x} extends past the end of the input ({:#x} bytes)",
+                index, offset, size, len
+            ),
+            Error::BadAlignment {
+                index,
+                offset,
+                align,
+            } => write!(
+                f,
+                "slice {} at offset {:#x} has alignment 2^{}, the maximum is 2^15",
+                index, offset, align
+            ),
+            Error::NoArches => write!(f, "no architectures to write"),
             Error::InvalidMachO(err) => write!(f, "{}", err),
             Error::DuplicatedArch(arch) => write!(f, "duplicated architecture {}", arch),
+            Error::MissingArches(arches) => {
+                write!(f, "missing architectures {}", arches.join(", "))
+            }
+            Error::Fat32Overflow(arch) => {
+                write!(f, "architecture {} does not fit in a fat32 header", arch)
+            }
+            Error::ArchMismatch { expected, found } => {
+                write!(f, "expected architecture {} but found {}", expected, found)
+            }
+            Error::MixedFiletypes(arches) => {
+                let arches: Vec<String> = arches
+                    .iter()
+                    .map(|(arch, filetype)| format!("{} ({})", arch, filetype_to_str(*filetype)))
+                    .collect();
+                write!(f, "slices have different filetypes: {}", arches.join(", "))
+            }
             #[cfg(feature = "bitcode")]
             Error::Bitcode(err) => err.fmt(f),
         }
@@ -31,8 +111,17 @@ impl error::Error for Error {
             Error::Io(err) => Some(err),
             Error::Goblin(err) => Some(err),
             Error::NotFatBinary => None,
+            Error::Truncated { .. } => None,
+            Error::TooManyArches { .. } => None,
+            Error::SliceOutOfBounds { .. } => None,
+            Error::BadAlignment { .. } => None,
+            Error::NoArches => None,
             Error::InvalidMachO(_) => None,
             Error::DuplicatedArch(_) => None,
+            Error::MissingArches(_) => None,
+            Error::Fat32Overflow(_) => None,
+            Error::ArchMismatch { .. } => None,
+            Error::MixedFiletypes(_) => None,
             #[cfg(feature = "bitcode")]
             Error::Bitcode(err) => Some(err),
         }
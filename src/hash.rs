@@ -0,0 +1,37 @@
+use std::io::{self, Write};
+
+use digest::Digest;
+
+/// Digests of every slice of a fat binary and of the whole fat binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FatDigests {
+    /// Digest of each thin binary paired with its arch name, in header order
+    pub slices: Vec<(String, Vec<u8>)>,
+    /// Digest of the whole fat binary, including the header and padding
+    pub fat: Vec<u8>,
+}
+
+/// A writer that feeds everything written to a hasher
+pub(crate) struct DigestWriter<D>(D);
+
+impl<D: Digest> DigestWriter<D> {
+    pub(crate) fn new() -> Self {
+        Self(D::new())
+    }
+
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+impl<D: Digest> Write for DigestWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,7 +1,33 @@
+mod arch;
+mod codesign;
+#[cfg(feature = "std-fs")]
+mod edit;
 mod error;
+#[cfg(feature = "digest")]
+mod hash;
+mod magic;
 mod read;
+mod repair;
+mod stream;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod verify;
 mod write;
 
+#[cfg(feature = "std-fs")]
+pub use self::edit::FatEditor;
 pub use self::error::Error;
-pub use self::read::FatReader;
-pub use self::write::FatWriter;
+#[cfg(feature = "digest")]
+pub use self::hash::FatDigests;
+pub use self::magic::{is_fat_magic, FAT_CIGAM, FAT_CIGAM_64, FAT_MAGIC, FAT_MAGIC_64};
+#[cfg(feature = "mmap")]
+pub use self::read::FatFile;
+pub use self::read::{diff_arch, ArchReport, FatArchInfo, FatInfo, FatReader, FatReport};
+pub use self::repair::repair;
+#[cfg(feature = "tokio")]
+pub use self::stream::AsyncFatStreamReader;
+pub use self::stream::FatStreamReader;
+pub use self::verify::Diagnostic;
+pub use self::write::{
+    DuplicatePolicy, FatFormat, FatWriter, FatWriterOptions, SortKey, SortOrder,
+};
@@ -0,0 +1,28 @@
+/// Magic number of a 32-bit fat header, as read in big-endian byte order
+pub const FAT_MAGIC: u32 = goblin::mach::fat::FAT_MAGIC;
+/// Byte-swapped [`FAT_MAGIC`]
+pub const FAT_CIGAM: u32 = goblin::mach::fat::FAT_CIGAM;
+/// Magic number of a 64-bit fat header, as read in big-endian byte order
+pub const FAT_MAGIC_64: u32 = FAT_MAGIC + 1;
+/// Byte-swapped [`FAT_MAGIC_64`]
+pub const FAT_CIGAM_64: u32 = FAT_MAGIC_64.swap_bytes();
+
+/// Check whether a big-endian `magic` is any of the fat header magic numbers
+pub fn is_fat_magic(magic: u32) -> bool {
+    matches!(magic, FAT_MAGIC | FAT_CIGAM | FAT_MAGIC_64 | FAT_CIGAM_64)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::mach::header::MH_MAGIC_64;
+
+    use super::is_fat_magic;
+
+    #[test]
+    fn test_is_fat_magic() {
+        for magic in [0xcafebabe, 0xbebafeca, 0xcafebabf, 0xbfbafeca] {
+            assert!(is_fat_magic(magic));
+        }
+        assert!(!is_fat_magic(MH_MAGIC_64));
+    }
+}
@@ -1,36 +1,592 @@
-use goblin::mach::{cputype::get_arch_from_flag, Mach, MultiArch};
+use std::io::Write;
+#[cfg(all(unix, feature = "std-fs"))]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(feature = "std-fs")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
+#[cfg(all(unix, feature = "std-fs"))]
+use goblin::mach::header::MH_EXECUTE;
+use goblin::{
+    archive::Archive,
+    mach::{
+        cputype::{
+            get_arch_from_flag, CpuSubType, CpuType, CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_ARM64_E,
+            CPU_SUBTYPE_ARM64_V8, CPU_SUBTYPE_ARM_ALL, CPU_SUBTYPE_ARM_V7, CPU_SUBTYPE_ARM_V7F,
+            CPU_SUBTYPE_ARM_V7S, CPU_SUBTYPE_MASK, CPU_SUBTYPE_MC680X0_ALL, CPU_SUBTYPE_X86_64_ALL,
+            CPU_SUBTYPE_X86_64_H, CPU_SUBTYPE_X86_ALL, CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_I386,
+            CPU_TYPE_MC680X0, CPU_TYPE_X86_64,
+        },
+        fat::{SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
+        header::MH_PIE,
+        MachO, MultiArch,
+    },
+};
+
+use crate::arch::{arch_name, display_arch_name, same_arch, ArchFamily};
+use crate::codesign::has_code_signature;
 use crate::error::Error;
+#[cfg(feature = "digest")]
+use crate::hash::FatDigests;
+use crate::magic::{FAT_CIGAM, FAT_CIGAM_64, FAT_MAGIC, FAT_MAGIC_64};
+use crate::verify::{check_entries, Diagnostic};
+use crate::write::{parse_macho_header, FatWriter, MAXSECTALIGN, SIZEOF_FAT_ARCH_64};
+
+/// A fat_arch or fat_arch_64 entry converted to host byte order
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FatArchEntry {
+    pub(crate) cputype: u32,
+    pub(crate) cpusubtype: u32,
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+    pub(crate) align: u32,
+    /// Listed past nfat_arch, see [`FatWriter::set_hide_arm64`](crate::FatWriter::set_hide_arm64)
+    pub(crate) hidden: bool,
+}
+
+impl FatArchEntry {
+    /// Get the slice of bytes this entry describes, or an empty slice if it's out of bounds
+    pub(crate) fn slice<'a>(&self, buffer: &'a [u8]) -> &'a [u8] {
+        let start = usize::try_from(self.offset).ok();
+        let size = usize::try_from(self.size).ok();
+        start
+            .zip(size)
+            .and_then(|(start, size)| buffer.get(start..start.checked_add(size)?))
+            .unwrap_or_default()
+    }
+}
+
+/// An architecture in a fat binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatArchInfo<'a> {
+    /// Architecture name, `None` if the cpu type is unknown
+    pub arch_name: Option<&'static str>,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    /// Capability bits of the cpusubtype, such as the arm64e pointer
+    /// authentication ABI version
+    pub capabilities: u32,
+    /// Offset of the slice in the fat binary
+    pub offset: u64,
+    /// Size of the slice
+    pub size: u64,
+    /// Slice alignment as a power of 2
+    pub align: u32,
+    /// Mach-O filetype of the slice, `None` for archives and bitcode
+    pub filetype: Option<u32>,
+    /// Whether the entry is hidden past nfat_arch like `lipo -hideARM64` does
+    pub hidden: bool,
+    /// Whether the slice has an `LC_CODE_SIGNATURE` load command
+    pub has_code_signature: bool,
+    data: &'a [u8],
+}
+
+impl<'a> FatArchInfo<'a> {
+    /// Get the thin binary of this architecture
+    ///
+    /// Empty if the slice lies outside of the fat binary.
+    pub fn slice(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Detailed description of a fat binary, like `lipo -detailed_info`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FatInfo<'a> {
+    /// Fat header magic, as read in big-endian byte order
+    pub magic: u32,
+    /// Whether the header uses 64-bit fat_arch_64 entries
+    pub is_fat64: bool,
+    pub arches: Vec<FatArchInfo<'a>>,
+}
+
+/// Owned description of an architecture in a fat binary, see [`FatArchInfo`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchReport {
+    /// Architecture name, `None` if the cpu type is unknown
+    pub arch_name: Option<String>,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    /// Capability bits of the cpusubtype, see [`FatArchInfo::capabilities`]
+    pub capabilities: u32,
+    /// Offset of the slice in the fat binary
+    pub offset: u64,
+    /// Size of the slice
+    pub size: u64,
+    /// Slice alignment as a power of 2
+    pub align: u32,
+    /// Mach-O filetype of the slice, `None` for archives and bitcode
+    pub filetype: Option<u32>,
+    /// Whether the entry is hidden past nfat_arch like `lipo -hideARM64` does
+    pub hidden: bool,
+    /// Whether the slice has an `LC_CODE_SIGNATURE` load command
+    pub has_code_signature: bool,
+}
+
+impl From<&FatArchInfo<'_>> for ArchReport {
+    fn from(arch: &FatArchInfo<'_>) -> Self {
+        Self {
+            arch_name: arch.arch_name.map(str::to_string),
+            cputype: arch.cputype,
+            cpusubtype: arch.cpusubtype,
+            capabilities: arch.capabilities,
+            offset: arch.offset,
+            size: arch.size,
+            align: arch.align,
+            filetype: arch.filetype,
+            hidden: arch.hidden,
+            has_code_signature: arch.has_code_signature,
+        }
+    }
+}
+
+/// Owned description of a fat binary and its structural problems, see [`FatInfo`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FatReport {
+    /// Fat header magic, as read in big-endian byte order
+    pub magic: u32,
+    /// Whether the header uses 64-bit fat_arch_64 entries
+    pub is_fat64: bool,
+    pub arches: Vec<ArchReport>,
+    /// See [`FatReader::verify`]
+    pub diagnostics: Vec<Diagnostic>,
+}
 
 /// Mach-O fat binary reader
 #[derive(Debug)]
 pub struct FatReader<'a> {
     buffer: &'a [u8],
+    magic: u32,
+    is_fat64: bool,
+    arches: Vec<FatArchEntry>,
     fat: MultiArch<'a>,
 }
 
 impl<'a> FatReader<'a> {
     /// Parse a Mach-O FAT binary from a buffer
+    ///
+    /// Both 32-bit and 64-bit fat headers are supported, as well as headers
+    /// that were mistakenly written in little-endian byte order. Malformed
+    /// headers fail with [`Error::Truncated`], [`Error::TooManyArches`] or
+    /// [`Error::BadAlignment`], slice placement is left to
+    /// [`FatReader::verify`].
     pub fn new(buffer: &'a [u8]) -> Result<Self, Error> {
-        match Mach::parse(buffer)? {
-            Mach::Fat(fat) => Ok(Self { buffer, fat }),
-            Mach::Binary(_) => Err(Error::NotFatBinary),
+        Self::with_stream_len(buffer, buffer.len() as u64)
+    }
+
+    /// Parse the start of a fat binary of `len` bytes, at least its fat_arch table
+    ///
+    /// Hidden arm64 entries are checked against `len` rather than the buffer,
+    /// so `buffer` only needs to extend up to the first slice.
+    pub(crate) fn with_stream_len(buffer: &'a [u8], len: u64) -> Result<Self, Error> {
+        let magic = read_u32(buffer, 0, false).ok_or(Error::NotFatBinary)?;
+        let (swapped, is_fat64) = match magic {
+            FAT_MAGIC => (false, false),
+            FAT_CIGAM => (true, false),
+            FAT_MAGIC_64 => (false, true),
+            FAT_CIGAM_64 => (true, true),
+            _ => return Err(Error::NotFatBinary),
+        };
+        let nfat_arch = read_u32(buffer, 4, swapped).ok_or(Error::Truncated {
+            needed: SIZEOF_FAT_HEADER as u64,
+            len: buffer.len() as u64,
+        })?;
+        let arch_size = if is_fat64 {
+            SIZEOF_FAT_ARCH_64
+        } else {
+            SIZEOF_FAT_ARCH
+        };
+        let max = (buffer.len() - SIZEOF_FAT_HEADER) / arch_size;
+        if nfat_arch as usize > max {
+            return Err(Error::TooManyArches {
+                count: nfat_arch,
+                max: u32::try_from(max).unwrap_or(u32::MAX),
+            });
+        }
+        let nfat_arch = nfat_arch as usize;
+        let entry = |index: usize| {
+            let base = SIZEOF_FAT_HEADER + index * arch_size;
+            // Callers check the bounds
+            let field = |offset| read_u32(buffer, base + offset, swapped).unwrap();
+            if is_fat64 {
+                FatArchEntry {
+                    cputype: field(0),
+                    cpusubtype: field(4),
+                    offset: (field(8) as u64) << 32 | field(12) as u64,
+                    size: (field(16) as u64) << 32 | field(20) as u64,
+                    align: field(24),
+                    hidden: false,
+                }
+            } else {
+                FatArchEntry {
+                    cputype: field(0),
+                    cpusubtype: field(4),
+                    offset: field(8) as u64,
+                    size: field(12) as u64,
+                    align: field(16),
+                    hidden: false,
+                }
+            }
+        };
+        let mut arches: Vec<FatArchEntry> = (0..nfat_arch).map(entry).collect();
+        // Larger alignments can't be laid out again, cctools lipo rejects them too
+        if let Some((index, arch)) = arches
+            .iter()
+            .enumerate()
+            .find(|(_, arch)| arch.align > MAXSECTALIGN)
+        {
+            return Err(Error::BadAlignment {
+                index,
+                offset: arch.offset,
+                align: arch.align,
+            });
+        }
+        // `lipo -hideARM64` lists arm64 slices past nfat_arch, before the first slice
+        let first_offset = arches.iter().map(|arch| arch.offset).min().unwrap_or(0);
+        for index in nfat_arch.. {
+            let table_end = SIZEOF_FAT_HEADER + (index + 1) * arch_size;
+            if table_end > buffer.len() || table_end as u64 > first_offset {
+                break;
+            }
+            let mut hidden = entry(index);
+            if hidden.cputype != CPU_TYPE_ARM64
+                || hidden.offset < table_end as u64
+                || hidden.align > MAXSECTALIGN
+                || hidden.size == 0
+                || hidden
+                    .offset
+                    .checked_add(hidden.size)
+                    .map_or(true, |end| end > len)
+            {
+                break;
+            }
+            hidden.hidden = true;
+            arches.push(hidden);
+        }
+        Ok(Self {
+            buffer,
+            magic,
+            is_fat64,
+            arches,
+            fat: MultiArch::new(buffer)?,
+        })
+    }
+
+    /// Parse a Mach-O FAT binary, rejecting slices that extend past the buffer
+    ///
+    /// [`FatReader::new`] accepts those so that [`FatReader::verify`] can
+    /// report them, this returns [`Error::SliceOutOfBounds`] instead.
+    pub fn new_strict(buffer: &'a [u8]) -> Result<Self, Error> {
+        let reader = Self::new(buffer)?;
+        let len = buffer.len() as u64;
+        for (index, arch) in reader.arches.iter().enumerate() {
+            if arch
+                .offset
+                .checked_add(arch.size)
+                .map_or(true, |end| end > len)
+            {
+                return Err(Error::SliceOutOfBounds {
+                    index,
+                    offset: arch.offset,
+                    size: arch.size,
+                    len,
+                });
+            }
+        }
+        Ok(reader)
+    }
+
+    pub(crate) fn arch_entries(&self) -> &[FatArchEntry] {
+        &self.arches
+    }
+
+    /// Iterate over the architectures in this fat binary, in header order
+    pub fn arches(&self) -> impl Iterator<Item = FatArchInfo<'a>> + '_ {
+        self.arches.iter().map(|arch| {
+            let data = arch.slice(self.buffer);
+            FatArchInfo {
+                arch_name: arch_name(arch.cputype, arch.cpusubtype),
+                cputype: arch.cputype,
+                cpusubtype: arch.cpusubtype,
+                capabilities: arch.cpusubtype & CPU_SUBTYPE_MASK,
+                offset: arch.offset,
+                size: arch.size,
+                align: arch.align,
+                filetype: parse_macho_header(data).map(|(_, _, filetype)| filetype),
+                hidden: arch.hidden,
+                has_code_signature: has_code_signature(data),
+                data,
+            }
+        })
+    }
+
+    /// Names of the architectures in header order, like `lipo -archs`
+    ///
+    /// Unknown architectures are named after their cputype and cpusubtype.
+    pub fn archs(&self) -> Vec<String> {
+        self.arches
+            .iter()
+            .map(|arch| display_arch_name(arch.cputype, arch.cpusubtype))
+            .collect()
+    }
+
+    /// Describe the fat header and every architecture in it
+    pub fn info(&self) -> FatInfo<'a> {
+        FatInfo {
+            magic: self.magic,
+            is_fat64: self.is_fat64,
+            arches: self.arches().collect(),
+        }
+    }
+
+    /// Describe the fat binary like [`FatReader::info`], without borrowing it
+    ///
+    /// Includes the diagnostics of [`FatReader::verify`].
+    pub fn report(&self) -> FatReport {
+        FatReport {
+            magic: self.magic,
+            is_fat64: self.is_fat64,
+            arches: self.arches().map(|arch| ArchReport::from(&arch)).collect(),
+            diagnostics: self.verify(),
         }
     }
 
     /// Extract thin binary by arch name
+    ///
+    /// Both the cputype and the cpusubtype must match, ignoring the
+    /// capability bits of the subtype.
     pub fn extract(&self, arch_name: &str) -> Option<&'a [u8]> {
-        if let Some((cpu_type, _cpu_subtype)) = get_arch_from_flag(arch_name) {
-            return self
-                .fat
-                .find_cputype(cpu_type)
-                .unwrap_or_default()
-                .map(|arch| arch.slice(self.buffer));
+        find_arch(&self.arches, arch_name).map(|arch| arch.slice(self.buffer))
+    }
+
+    /// Every architecture of a family, in header order
+    ///
+    /// Family names like `arm`, `arm64`, `x86_64` or `i386` match all
+    /// cpusubtypes of their cputype, so `arm` selects armv7, armv7s and every
+    /// other 32-bit ARM slice. Other names match just that architecture.
+    /// Returns an empty list for an unknown name.
+    pub fn extract_family(&self, family: &str) -> Vec<FatArchInfo<'a>> {
+        let Some(family) = ArchFamily::from_name(family) else {
+            return Vec::new();
+        };
+        self.arches()
+            .filter(|arch| family.contains(arch.cputype, arch.cpusubtype))
+            .collect()
+    }
+
+    /// Write the requested architectures to `writer`
+    ///
+    /// A single architecture is written as a thin binary, several as a new fat
+    /// binary with freshly computed offsets and alignment, like `lipo -thin`
+    /// and `lipo -extract`. Fails with [`Error::MissingArches`] if any of
+    /// them is absent.
+    pub fn thin<W: Write>(&self, arch_names: &[&str], writer: &mut W) -> Result<(), Error> {
+        self.covers(arch_names)?;
+        if let [arch_name] = arch_names {
+            // Checked by `covers`
+            let slice = self.extract(arch_name).unwrap();
+            writer.write_all(slice)?;
+            return Ok(());
+        }
+        let mut fat = FatWriter::new();
+        for arch_name in arch_names {
+            fat.add_borrowed(self.extract(arch_name).unwrap())?;
+        }
+        fat.write_to(writer)?;
+        Ok(())
+    }
+
+    /// Extract the static archive of an architecture from a fat static library
+    pub fn extract_archive(&self, arch_name: &str) -> Result<Archive<'a>, Error> {
+        let slice = self
+            .extract(arch_name)
+            .ok_or_else(|| Error::MissingArches(vec![arch_name.to_string()]))?;
+        Ok(Archive::parse(slice)?)
+    }
+
+    /// Extract the thin binary a CPU of the given type would run
+    ///
+    /// Slices are graded the way dyld does: an exact subtype wins, then
+    /// the subtypes the CPU can fall back to, in order of preference.
+    pub fn extract_best(&self, cpu_type: CpuType, cpu_subtype: CpuSubType) -> Option<&'a [u8]> {
+        let preferred = compatible_subtypes(cpu_type, cpu_subtype & !CPU_SUBTYPE_MASK);
+        self.arches
+            .iter()
+            .filter(|arch| arch.cputype == cpu_type)
+            .filter_map(|arch| {
+                let subtype = arch.cpusubtype & !CPU_SUBTYPE_MASK;
+                let grade = preferred.iter().position(|&s| s == subtype)?;
+                Some((grade, arch))
+            })
+            .min_by_key(|(grade, _)| *grade)
+            .map(|(_, arch)| arch.slice(self.buffer))
+    }
+
+    /// Extract the thin binary at `index` in header order
+    pub fn extract_at(&self, index: usize) -> Option<&'a [u8]> {
+        self.arches.get(index).map(|arch| arch.slice(self.buffer))
+    }
+
+    /// Write every thin binary to `<stem>_<arch>` in `dir`, returning the paths written
+    ///
+    /// Unknown arches are named by their cputype and cpusubtype like
+    /// [`FatReader::archs`]. Executable slices are written with mode 0o755 on
+    /// Unix.
+    #[cfg(feature = "std-fs")]
+    pub fn extract_all_to_dir<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        stem: &str,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::with_capacity(self.arches.len());
+        for arch in self.arches() {
+            let name = display_arch_name(arch.cputype, arch.cpusubtype);
+            let path = dir.join(format!("{}_{}", stem, name));
+            fs::write(&path, arch.slice())?;
+            #[cfg(unix)]
+            if arch.filetype == Some(MH_EXECUTE) {
+                let mut perm = fs::metadata(&path)?.permissions();
+                perm.set_mode(0o755);
+                fs::set_permissions(&path, perm)?;
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Copy every thin binary out of the fat binary, paired with its arch name
+    ///
+    /// This allocates a copy of each slice, roughly the size of the whole
+    /// input; prefer [`FatReader::extract`] when the input buffer outlives the
+    /// results.
+    pub fn extract_all_owned(&self) -> Vec<(String, Vec<u8>)> {
+        self.slices()
+            .map(|(name, slice)| (name, slice.to_vec()))
+            .collect()
+    }
+
+    /// DEFLATE-compressed size of every thin binary, paired with its arch name
+    ///
+    /// This is a rough compressibility estimate for deciding between shipping
+    /// the fat binary or per-arch downloads. Every slice is compressed in full.
+    #[cfg(feature = "flate2")]
+    pub fn compressed_sizes(&self) -> Vec<(String, u64)> {
+        use flate2::{write::DeflateEncoder, Compression};
+        use std::io::Write;
+
+        self.slices()
+            .map(|(name, slice)| {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                // Writing into a `Vec` can't fail
+                encoder.write_all(slice).unwrap();
+                let compressed = encoder.finish().unwrap();
+                (name, compressed.len() as u64)
+            })
+            .collect()
+    }
+
+    /// SHA-256 digest of every thin binary and of the whole fat binary
+    #[cfg(feature = "digest")]
+    pub fn slice_digests(&self) -> FatDigests {
+        self.slice_digests_with::<sha2::Sha256>()
+    }
+
+    /// Digest of every thin binary and of the whole fat binary, using hasher `D`
+    #[cfg(feature = "digest")]
+    pub fn slice_digests_with<D: digest::Digest>(&self) -> FatDigests {
+        FatDigests {
+            slices: self
+                .slices()
+                .map(|(name, slice)| (name, D::digest(slice).to_vec()))
+                .collect(),
+            fat: D::digest(self.buffer).to_vec(),
+        }
+    }
+
+    /// Every slice paired with its arch name, unknown arches named by cputype and cpusubtype
+    fn slices(&self) -> impl Iterator<Item = (String, &'a [u8])> + '_ {
+        self.arches().map(|arch| {
+            (
+                display_arch_name(arch.cputype, arch.cpusubtype),
+                arch.slice(),
+            )
+        })
+    }
+
+    /// Check whether the slice for an arch is a position-independent executable
+    ///
+    /// Returns `None` if the arch is missing or the slice is not a Mach-O binary.
+    pub fn is_pie(&self, arch_name: &str) -> Option<bool> {
+        let slice = self.extract(arch_name)?;
+        let macho = MachO::parse(slice, 0).ok()?;
+        Some(macho.header.flags & MH_PIE != 0)
+    }
+
+    /// Check that every required architecture is present
+    ///
+    /// Returns [`Error::MissingArches`] listing all absent architectures.
+    pub fn covers(&self, required: &[&str]) -> Result<(), Error> {
+        let missing: Vec<String> = required
+            .iter()
+            .filter(|arch| self.extract(arch).is_none())
+            .map(|arch| arch.to_string())
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingArches(missing))
+        }
+    }
+
+    /// Check the fat header for structural problems
+    ///
+    /// Returns an empty list for a well-formed fat binary.
+    pub fn verify(&self) -> Vec<Diagnostic> {
+        check_entries(
+            &self.arches,
+            self.table_end() as u64,
+            self.buffer.len() as u64,
+        )
+    }
+
+    /// Offset of the end of the fat_arch table, including hidden entries
+    pub(crate) fn table_end(&self) -> usize {
+        let arch_size = if self.is_fat64 {
+            SIZEOF_FAT_ARCH_64
+        } else {
+            SIZEOF_FAT_ARCH
+        };
+        SIZEOF_FAT_HEADER + self.arches.len() * arch_size
+    }
+
+    /// Bytes between the end of the fat_arch table and the first slice
+    ///
+    /// These are normally zero padding, but some tools stash data there.
+    pub fn header_gap_bytes(&self) -> &'a [u8] {
+        let start = self.table_end();
+        let end = self
+            .arches
+            .iter()
+            .map(|arch| usize::try_from(arch.offset).unwrap_or(usize::MAX))
+            .min()
+            .unwrap_or(start)
+            .min(self.buffer.len());
+        if end <= start {
+            return &[];
         }
-        None
+        &self.buffer[start..end]
     }
 }
 
+/// goblin's view of the fat header
+///
+/// Only the `nfat_arch` entries of big-endian 32-bit headers are seen this
+/// way, prefer [`FatReader::arches`] which handles every header format.
 impl<'a> std::ops::Deref for FatReader<'a> {
     type Target = MultiArch<'a>;
 
@@ -45,14 +601,102 @@ impl<'a> std::ops::DerefMut for FatReader<'a> {
     }
 }
 
+/// Find the first byte offset where an arch's slice differs between two fat binaries
+///
+/// Returns `None` if the slices are identical. If one slice is a prefix of the
+/// other, the length of the shorter slice is returned.
+pub fn diff_arch(a: &[u8], b: &[u8], arch: &str) -> Result<Option<usize>, Error> {
+    let missing = || Error::MissingArches(vec![arch.to_string()]);
+    let a = FatReader::new(a)?.extract(arch).ok_or_else(missing)?;
+    let b = FatReader::new(b)?.extract(arch).ok_or_else(missing)?;
+    let first_diff = a.iter().zip(b).position(|(x, y)| x != y);
+    Ok(first_diff.or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len()))))
+}
+
+/// A memory-mapped fat binary on disk
+///
+/// Only the pages of the slices that are actually accessed get loaded, so
+/// extracting one slice doesn't read the whole file into memory.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct FatFile {
+    mmap: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl FatFile {
+    /// Memory-map a fat binary
+    ///
+    /// The file must not be modified while it's mapped, as that would change
+    /// the bytes behind slices handed out by the reader.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        let file = std::fs::File::open(path)?;
+        // Safety: see above, the file is assumed not to change while mapped
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self { mmap })
+    }
+
+    /// Parse the mapped fat binary
+    pub fn reader(&self) -> Result<FatReader<'_>, Error> {
+        FatReader::new(&self.mmap)
+    }
+}
+
+#[inline]
+/// Find an arch by name, ignoring the capability bits of the cpusubtype
+pub(crate) fn find_arch<'a>(
+    arches: &'a [FatArchEntry],
+    arch_name: &str,
+) -> Option<&'a FatArchEntry> {
+    let (cpu_type, cpu_subtype) = get_arch_from_flag(arch_name)?;
+    arches
+        .iter()
+        .find(|arch| same_arch((arch.cputype, arch.cpusubtype), (cpu_type, cpu_subtype)))
+}
+
+/// Subtypes a CPU can run, best first
+fn compatible_subtypes(cpu_type: CpuType, cpu_subtype: CpuSubType) -> Vec<CpuSubType> {
+    match (cpu_type, cpu_subtype) {
+        (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E) => vec![
+            CPU_SUBTYPE_ARM64_E,
+            CPU_SUBTYPE_ARM64_V8,
+            CPU_SUBTYPE_ARM64_ALL,
+        ],
+        (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_V8) => vec![CPU_SUBTYPE_ARM64_V8, CPU_SUBTYPE_ARM64_ALL],
+        (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_H) => {
+            vec![CPU_SUBTYPE_X86_64_H, CPU_SUBTYPE_X86_64_ALL]
+        }
+        (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S | CPU_SUBTYPE_ARM_V7F) => {
+            vec![cpu_subtype, CPU_SUBTYPE_ARM_V7, CPU_SUBTYPE_ARM_ALL]
+        }
+        (CPU_TYPE_X86_64 | CPU_TYPE_I386, _) => vec![cpu_subtype, CPU_SUBTYPE_X86_ALL],
+        (CPU_TYPE_MC680X0, _) => vec![cpu_subtype, CPU_SUBTYPE_MC680X0_ALL],
+        // Every other cpu type numbers its generic `*_ALL` subtype 0, e.g.
+        // CPU_SUBTYPE_ARM_ALL and CPU_SUBTYPE_POWERPC_ALL
+        _ => vec![cpu_subtype, 0],
+    }
+}
+
+fn read_u32(buffer: &[u8], offset: usize, swapped: bool) -> Option<u32> {
+    let bytes = buffer.get(offset..offset.checked_add(4)?)?;
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    // The fat header is big-endian unless it was written byte-swapped
+    Some(if swapped {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use std::fs;
 
     use goblin::Object;
 
-    use super::FatReader;
+    use super::{diff_arch, FatReader};
     use crate::error::Error;
+    use crate::write::FatWriter;
 
     #[test]
     fn test_fat_reader_dylib() {
@@ -92,6 +736,61 @@ mod test {
         assert!(matches!(reader.unwrap_err(), Error::NotFatBinary));
     }
 
+    #[test]
+    fn test_fat_reader_malformed() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let set = |buf: &mut Vec<u8>, offset: usize, value: u32| {
+            buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        };
+
+        assert!(matches!(
+            FatReader::new(&buf[..6]),
+            Err(Error::Truncated { needed: 8, len: 6 })
+        ));
+        assert!(matches!(
+            FatReader::new(&buf[..40]),
+            Err(Error::TooManyArches { count: 2, max: 1 })
+        ));
+        let mut bad = buf.clone();
+        set(&mut bad, 4, u32::MAX);
+        assert!(matches!(
+            FatReader::new(&bad),
+            Err(Error::TooManyArches {
+                count: u32::MAX,
+                ..
+            })
+        ));
+
+        let mut bad = buf.clone();
+        set(&mut bad, 44, 64);
+        assert!(matches!(
+            FatReader::new(&bad),
+            Err(Error::BadAlignment {
+                index: 1,
+                align: 64,
+                ..
+            })
+        ));
+
+        let mut bad = buf.clone();
+        set(&mut bad, 36, 0x100_0000);
+        assert!(FatReader::new(&bad).is_ok());
+        assert!(matches!(
+            FatReader::new_strict(&bad),
+            Err(Error::SliceOutOfBounds {
+                index: 1,
+                offset: 0x100_0000,
+                ..
+            })
+        ));
+        assert!(FatReader::new_strict(&buf).is_ok());
+
+        // Arbitrary prefixes never panic
+        for len in 0..buf.len().min(4096) {
+            let _ = FatReader::new(&buf[..len]).map(|reader| reader.report());
+        }
+    }
+
     #[test]
     fn test_fat_reader_extract_dylib() {
         let buf = fs::read("tests/fixtures/simplefat.dylib").unwrap();
@@ -127,4 +826,457 @@ mod test {
         let arm64_obj = Object::parse(arm64).unwrap();
         assert!(matches!(arm64_obj, Object::Archive(_)));
     }
+
+    #[test]
+    fn test_fat_reader_header_gap_bytes() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let gap = reader.header_gap_bytes();
+        assert_eq!(gap.len(), 16384 - 48);
+        assert!(gap.iter().all(|b| *b == 0));
+
+        let buf = fs::read("tests/fixtures/simplefat_padded").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let gap = reader.header_gap_bytes();
+        assert_eq!(gap.len(), 16384 - 48);
+        assert!(gap.starts_with(b"fat-macho header padding"));
+    }
+
+    #[test]
+    fn test_fat_reader_covers() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        assert!(reader.covers(&["x86_64", "arm64"]).is_ok());
+
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        let mut buf = Vec::new();
+        fat.write_to(&mut buf).unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let err = reader.covers(&["x86_64", "arm64"]).unwrap_err();
+        assert!(matches!(err, Error::MissingArches(arches) if arches == ["arm64"]));
+    }
+
+    #[test]
+    fn test_fat_reader_is_pie() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        assert_eq!(reader.is_pie("x86_64"), Some(true));
+        assert_eq!(reader.is_pie("arm64"), Some(true));
+        assert_eq!(reader.is_pie("i386"), None);
+
+        let buf = fs::read("tests/fixtures/simplefat.dylib").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        assert_eq!(reader.is_pie("x86_64"), Some(false));
+        assert_eq!(reader.is_pie("arm64"), Some(false));
+
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        assert_eq!(reader.is_pie("x86_64"), None);
+    }
+
+    #[test]
+    fn test_fat_reader_extract_all_owned() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let slices = reader.extract_all_owned();
+        assert_eq!(slices.len(), 2);
+        for (arch, data) in &slices {
+            assert_eq!(reader.extract(arch).unwrap(), &data[..]);
+        }
+
+        // Unknown arches get distinct names
+        let mut fat = FatWriter::new();
+        fat.add_with_arch(vec![1; 16], 0x1234, 1, 2).unwrap();
+        fat.add_with_arch(vec![2; 16], 0x1234, 2, 2).unwrap();
+        let mut buf = Vec::new();
+        fat.write_to(&mut buf).unwrap();
+        let slices = FatReader::new(&buf).unwrap().extract_all_owned();
+        assert_eq!(
+            slices,
+            [
+                ("cputype 4660 cpusubtype 1".to_string(), vec![1; 16]),
+                ("cputype 4660 cpusubtype 2".to_string(), vec![2; 16]),
+            ]
+        );
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn test_fat_reader_compressed_sizes() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let sizes = reader.compressed_sizes();
+        assert_eq!(sizes.len(), 2);
+        for (arch, size) in sizes {
+            assert!(size <= reader.extract(&arch).unwrap().len() as u64);
+        }
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_fat_reader_slice_digests() {
+        use sha2::{Digest, Sha256, Sha512};
+
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let digests = reader.slice_digests();
+        assert_eq!(digests.fat, Sha256::digest(&buf).to_vec());
+        assert_eq!(digests.slices.len(), 2);
+        let (arch, digest) = &digests.slices[1];
+        assert_eq!(
+            digest,
+            &Sha256::digest(reader.extract(arch).unwrap()).to_vec()
+        );
+        assert_eq!(
+            reader.slice_digests_with::<Sha512>().fat.len(),
+            Sha512::output_size()
+        );
+    }
+
+    /// Rewrite the big-endian fat32 header of `buf` with another encoding
+    fn reencode_header(buf: &[u8], is_fat64: bool, swapped: bool) -> Vec<u8> {
+        let nfat_arch = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+        let mut fields = vec![if is_fat64 { 0xcafebabf } else { 0xcafebabe }, nfat_arch];
+        for i in 0..nfat_arch as usize {
+            let arch = &buf[8 + i * 20..28 + i * 20];
+            let field = |n: usize| u32::from_be_bytes(arch[n * 4..n * 4 + 4].try_into().unwrap());
+            if is_fat64 {
+                fields.extend([field(0), field(1), 0, field(2), 0, field(3), field(4), 0]);
+            } else {
+                fields.extend([field(0), field(1), field(2), field(3), field(4)]);
+            }
+        }
+        let mut out = buf.to_vec();
+        out[..fields.len() * 4].fill(0);
+        for (i, field) in fields.iter().enumerate() {
+            let bytes = if swapped {
+                field.to_le_bytes()
+            } else {
+                field.to_be_bytes()
+            };
+            out[i * 4..i * 4 + 4].copy_from_slice(&bytes);
+        }
+        out
+    }
+
+    #[test]
+    fn test_fat_reader_magic_encodings() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let x86_64 = reader.extract("x86_64").unwrap();
+        let arm64 = reader.extract("arm64").unwrap();
+        for (is_fat64, swapped) in [(false, true), (true, false), (true, true)] {
+            let encoded = reencode_header(&buf, is_fat64, swapped);
+            let reader = FatReader::new(&encoded).unwrap();
+            assert_eq!(reader.extract("x86_64").unwrap(), x86_64);
+            assert_eq!(reader.extract("arm64").unwrap(), arm64);
+        }
+    }
+
+    #[test]
+    fn test_diff_arch() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        assert_eq!(diff_arch(&buf, &buf, "x86_64").unwrap(), None);
+
+        let mut changed = buf.clone();
+        // The x86_64 slice starts at 0x4000
+        changed[0x4000 + 100] ^= 0xff;
+        assert_eq!(diff_arch(&buf, &changed, "x86_64").unwrap(), Some(100));
+        assert_eq!(diff_arch(&buf, &changed, "arm64").unwrap(), None);
+        assert!(matches!(
+            diff_arch(&buf, &changed, "i386"),
+            Err(Error::MissingArches(_))
+        ));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_fat_file_open() {
+        use super::FatFile;
+
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let file = FatFile::open("tests/fixtures/simplefat").unwrap();
+        let mapped = file.reader().unwrap();
+        assert_eq!(mapped.extract("x86_64"), reader.extract("x86_64"));
+        assert_eq!(mapped.extract("arm64"), reader.extract("arm64"));
+
+        assert!(matches!(
+            FatFile::open("tests/fixtures/thin_x86_64")
+                .unwrap()
+                .reader(),
+            Err(Error::NotFatBinary)
+        ));
+    }
+
+    #[test]
+    fn test_fat_reader_arches() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let arches: Vec<_> = reader.arches().collect();
+        let names: Vec<_> = arches.iter().map(|arch| arch.arch_name).collect();
+        assert_eq!(names, [Some("x86_64"), Some("x86_64h"), Some("arm64")]);
+        let x86_64 = &arches[0];
+        assert_eq!(x86_64.offset, 0x4000);
+        assert_eq!(x86_64.size, 49424);
+        assert_eq!(x86_64.align, 14);
+        assert_eq!(x86_64.slice(), &buf[0x4000..0x4000 + 49424]);
+        assert_eq!(reader.archs(), ["x86_64", "x86_64h", "arm64"]);
+    }
+
+    #[test]
+    fn test_fat_reader_info() {
+        use goblin::mach::header::MH_EXECUTE;
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let info = reader.info();
+        assert_eq!(info.magic, crate::FAT_MAGIC);
+        assert!(!info.is_fat64);
+        assert_eq!(info.arches.len(), 3);
+        assert!(info
+            .arches
+            .iter()
+            .all(|arch| arch.filetype == Some(MH_EXECUTE)));
+
+        let encoded = reencode_header(&buf, true, false);
+        let reader = FatReader::new(&encoded).unwrap();
+        let info = reader.info();
+        assert_eq!(info.magic, crate::FAT_MAGIC_64);
+        assert!(info.is_fat64);
+        assert_eq!(info.arches[2].arch_name, Some("arm64"));
+        assert_eq!(info.arches[2].offset, 147456);
+
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let info = reader.info();
+        assert!(info.arches.iter().all(|arch| arch.filetype.is_none()));
+
+        let report = reader.report();
+        assert_eq!(report.arches.len(), info.arches.len());
+        assert_eq!(
+            report.arches[0].arch_name.as_deref(),
+            info.arches[0].arch_name
+        );
+        assert!(report.diagnostics.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_fat_report_serde() {
+        use super::FatReport;
+        use crate::verify::Diagnostic;
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let mut report = FatReader::new(&buf).unwrap().report();
+        report.diagnostics.push(Diagnostic::Overlap {
+            first: 0,
+            second: 1,
+        });
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains(r#""arch_name":"x86_64h""#));
+        assert_eq!(serde_json::from_str::<FatReport>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn test_fat_reader_extract_subtype() {
+        use crate::test_support::minimal_macho;
+        use goblin::mach::cputype::{
+            CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_ARM64_E, CPU_SUBTYPE_MC680X0_ALL,
+            CPU_SUBTYPE_X86_64_ALL, CPU_SUBTYPE_X86_64_H, CPU_TYPE_ARM64, CPU_TYPE_MC680X0,
+            CPU_TYPE_X86_64,
+        };
+
+        let arm64 = minimal_macho(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL);
+        // arm64e with the ptrauth ABI capability bit set
+        let arm64e = minimal_macho(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E | 0x8000_0000);
+        let x86_64 = minimal_macho(CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL);
+        let mut fat = FatWriter::new();
+        fat.add(arm64e.clone()).unwrap();
+        fat.add(arm64.clone()).unwrap();
+        fat.add(x86_64.clone()).unwrap();
+        let mut buf = Vec::new();
+        fat.write_to(&mut buf).unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+
+        assert_eq!(reader.extract("arm64"), Some(&arm64[..]));
+        assert_eq!(reader.extract("arm64e"), Some(&arm64e[..]));
+        assert_eq!(reader.extract("x86_64h"), None);
+
+        let best = |cpu_type, cpu_subtype| reader.extract_best(cpu_type, cpu_subtype);
+        assert_eq!(best(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E), Some(&arm64e[..]));
+        assert_eq!(
+            best(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL),
+            Some(&arm64[..])
+        );
+        assert_eq!(
+            best(CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_H),
+            Some(&x86_64[..])
+        );
+        assert_eq!(
+            best(CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
+            Some(&x86_64[..])
+        );
+
+        // CPU_SUBTYPE_MC680X0_ALL is 1 rather than 0
+        let mut fat = FatWriter::new();
+        fat.add_with_arch(vec![0; 16], CPU_TYPE_MC680X0, CPU_SUBTYPE_MC680X0_ALL, 2)
+            .unwrap();
+        let mut buf = Vec::new();
+        fat.write_to(&mut buf).unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let mc68040 = 2;
+        assert_eq!(
+            reader.extract_best(CPU_TYPE_MC680X0, mc68040),
+            Some(&[0; 16][..])
+        );
+    }
+
+    #[test]
+    fn test_fat_reader_extract_at() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        assert_eq!(reader.extract_at(0), reader.extract("x86_64"));
+        assert_eq!(reader.extract_at(1), reader.extract("arm64"));
+        assert_eq!(reader.extract_at(2), None);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_fat_reader_extract_all_to_dir() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        fs::create_dir_all("tests/output/extract_all").unwrap();
+        let paths = reader
+            .extract_all_to_dir("tests/output/extract_all", "simple")
+            .unwrap();
+        assert_eq!(
+            paths,
+            [
+                "tests/output/extract_all/simple_x86_64",
+                "tests/output/extract_all/simple_arm64"
+            ]
+            .map(std::path::PathBuf::from)
+        );
+        let x86_64 = fs::read(&paths[0]).unwrap();
+        assert_eq!(reader.extract("x86_64"), Some(&x86_64[..]));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&paths[1]).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn test_fat_reader_verify() {
+        use crate::verify::Diagnostic;
+
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        assert!(FatReader::new(&buf).unwrap().verify().is_empty());
+
+        let set = |buf: &mut Vec<u8>, offset: usize, value: u32| {
+            buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        // Move arm64 onto x86_64 and make it a second x86_64 slice
+        let mut bad = buf.clone();
+        set(&mut bad, 28, 0x0100_0007);
+        set(&mut bad, 32, 3);
+        set(&mut bad, 36, 16384);
+        let reader = FatReader::new(&bad).unwrap();
+        assert_eq!(
+            reader.verify(),
+            [
+                Diagnostic::Overlap {
+                    first: 0,
+                    second: 1
+                },
+                Diagnostic::DuplicateArch {
+                    first: 0,
+                    second: 1
+                },
+            ]
+        );
+
+        let mut bad = buf.clone();
+        set(&mut bad, 16, 4);
+        set(&mut bad, 36, 49153);
+        set(&mut bad, 40, 0x10_0000);
+        let diagnostics = FatReader::new(&bad).unwrap().verify();
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::OverlapsHeader {
+                    index: 0,
+                    offset: 4
+                },
+                Diagnostic::Misaligned {
+                    index: 0,
+                    offset: 4,
+                    align: 14
+                },
+                Diagnostic::Truncated {
+                    index: 1,
+                    end: 49153 + 0x10_0000,
+                    len: buf.len() as u64
+                },
+                Diagnostic::Misaligned {
+                    index: 1,
+                    offset: 49153,
+                    align: 14
+                },
+            ]
+        );
+
+        let mut bad = buf.clone();
+        set(&mut bad, 36, 0x100_0000);
+        assert_eq!(
+            FatReader::new(&bad).unwrap().verify(),
+            [Diagnostic::OffsetBeyondEof {
+                index: 1,
+                offset: 0x100_0000
+            }]
+        );
+    }
+
+    #[test]
+    fn test_fat_reader_extract_archive() {
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let archive = reader.extract_archive("arm64").unwrap();
+        assert!(archive.len() > 0);
+        assert!(matches!(
+            reader.extract_archive("i386"),
+            Err(Error::MissingArches(_))
+        ));
+
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        assert!(reader.extract_archive("arm64").is_err());
+    }
+
+    #[test]
+    fn test_fat_reader_thin() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+
+        let mut out = Vec::new();
+        reader.thin(&["arm64"], &mut out).unwrap();
+        assert_eq!(reader.extract("arm64"), Some(&out[..]));
+
+        let mut out = Vec::new();
+        reader.thin(&["x86_64", "arm64"], &mut out).unwrap();
+        let subset = FatReader::new(&out).unwrap();
+        assert_eq!(subset.arches().count(), 2);
+        assert_eq!(subset.extract("x86_64"), reader.extract("x86_64"));
+        assert_eq!(subset.extract("arm64"), reader.extract("arm64"));
+        assert!(subset.verify().is_empty());
+
+        assert!(matches!(
+            reader.thin(&["x86_64", "i386"], &mut Vec::new()),
+            Err(Error::MissingArches(arches)) if arches == ["i386"]
+        ));
+    }
 }
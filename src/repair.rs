@@ -0,0 +1,150 @@
+use goblin::{
+    archive::{Archive, MAGIC as AR_MAGIC},
+    mach::{
+        header::{MH_CIGAM, MH_CIGAM_64, MH_MAGIC, MH_MAGIC_64},
+        Mach,
+    },
+    Object,
+};
+
+use crate::arch::{display_arch_name, same_arch};
+use crate::error::Error;
+use crate::read::{FatArchEntry, FatReader};
+use crate::write::{FatFormat, FatWriter, FatWriterOptions};
+
+/// Rewrite a fat binary whose fat_arch offsets may be wrong
+///
+/// The declared slice sizes are trusted, but when a slice isn't found at its
+/// declared offset the rest of the file is searched for a Mach-O header or
+/// archive of the matching architecture. The slices are then written out into
+/// a correctly laid out fat binary, keeping the fat64 header and hidden arm64
+/// entries of the input.
+pub fn repair(buffer: &[u8]) -> Result<Vec<u8>, Error> {
+    let reader = FatReader::new(buffer)?;
+    let arches = reader.arch_entries();
+    let format = if reader.info().is_fat64 {
+        FatFormat::Fat64
+    } else {
+        FatFormat::Auto
+    };
+    let options = FatWriterOptions::new()
+        .format(format)
+        .hide_arm64(arches.iter().any(|arch| arch.hidden));
+    let mut writer = FatWriter::with_options(options);
+    let start = reader.table_end();
+    for arch in arches {
+        let slice = locate_slice(buffer, arch, start).ok_or_else(|| {
+            let name = display_arch_name(arch.cputype, arch.cpusubtype);
+            Error::InvalidMachO(format!("unable to locate slice for architecture {}", name))
+        })?;
+        writer.add_borrowed(slice)?;
+    }
+    let mut out = Vec::new();
+    writer.write_to(&mut out)?;
+    Ok(out)
+}
+
+fn locate_slice<'a>(buffer: &'a [u8], arch: &FatArchEntry, start: usize) -> Option<&'a [u8]> {
+    let size = usize::try_from(arch.size).ok()?;
+    let declared = usize::try_from(arch.offset).ok();
+    // Try the declared offset first, then every 4 byte boundary after the header
+    declared
+        .into_iter()
+        .chain((start..buffer.len()).step_by(4))
+        .filter_map(|offset| buffer.get(offset..offset.checked_add(size)?))
+        .find(|slice| slice_matches(slice, (arch.cputype, arch.cpusubtype)))
+}
+
+fn slice_matches(slice: &[u8], arch: (u32, u32)) -> bool {
+    let magic = match slice.get(..4) {
+        Some(magic) => u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]),
+        None => return false,
+    };
+    if ![MH_MAGIC, MH_CIGAM, MH_MAGIC_64, MH_CIGAM_64].contains(&magic)
+        && !slice.starts_with(AR_MAGIC)
+    {
+        return false;
+    }
+    let found = match Object::parse(slice) {
+        Ok(Object::Mach(Mach::Binary(obj))) => Some((obj.header.cputype, obj.header.cpusubtype)),
+        Ok(Object::Archive(ar)) => archive_arch(slice, &ar),
+        _ => None,
+    };
+    found.is_some_and(|found| same_arch(found, arch))
+}
+
+fn archive_arch(buffer: &[u8], ar: &Archive) -> Option<(u32, u32)> {
+    ar.members().into_iter().find_map(|member| {
+        let bytes = ar.extract(member, buffer).ok()?;
+        match Object::parse(bytes).ok()? {
+            Object::Mach(Mach::Binary(obj)) => Some((obj.header.cputype, obj.header.cpusubtype)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::repair;
+    use crate::read::FatReader;
+    use crate::write::{FatFormat, FatWriter, FatWriterOptions};
+    use crate::FAT_MAGIC_64;
+
+    #[test]
+    fn test_repair_too_small_offset() {
+        let x86_64 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let arm64 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let (x86_64_offset, arm64_offset) = (0x1000, 0x8000);
+        let mut buf = vec![0; arm64_offset + arm64.len()];
+        let header: [u32; 12] = [
+            0xcafebabe,
+            2,
+            0x01000007,
+            3,
+            // Declared offset points before the real x86_64 slice
+            0x400,
+            x86_64.len() as u32,
+            12,
+            0x0100000c,
+            0,
+            arm64_offset as u32,
+            arm64.len() as u32,
+            14,
+        ];
+        for (i, field) in header.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+        }
+        buf[x86_64_offset..x86_64_offset + x86_64.len()].copy_from_slice(&x86_64);
+        buf[arm64_offset..].copy_from_slice(&arm64);
+
+        let repaired = repair(&buf).unwrap();
+        let reader = FatReader::new(&repaired).unwrap();
+        assert_eq!(reader.extract("x86_64").unwrap(), &x86_64[..]);
+        assert_eq!(reader.extract("arm64").unwrap(), &arm64[..]);
+    }
+
+    #[test]
+    fn test_repair_fat64_matches_cpusubtype() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let x86_64 = reader.extract("x86_64").unwrap();
+        let x86_64h = reader.extract("x86_64h").unwrap();
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().format(FatFormat::Fat64));
+        fat.add(x86_64).unwrap();
+        fat.add(x86_64h).unwrap();
+        let mut buf = Vec::new();
+        fat.write_to(&mut buf).unwrap();
+        assert_eq!(FatReader::new(&buf).unwrap().archs(), ["x86_64", "x86_64h"]);
+        // Point the x86_64 entry at the x86_64h slice, which has the same cputype
+        let x86_64h_offset = buf[48..56].to_vec();
+        buf[16..24].copy_from_slice(&x86_64h_offset);
+
+        let repaired = repair(&buf).unwrap();
+        assert_eq!(&repaired[..4], FAT_MAGIC_64.to_be_bytes());
+        let reader = FatReader::new(&repaired).unwrap();
+        assert_eq!(reader.extract("x86_64").unwrap(), x86_64);
+        assert_eq!(reader.extract("x86_64h").unwrap(), x86_64h);
+    }
+}
@@ -0,0 +1,302 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use goblin::mach::fat::{SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite};
+
+use crate::arch::display_arch_name;
+use crate::error::Error;
+use crate::magic::{is_fat_magic, FAT_CIGAM, FAT_CIGAM_64, FAT_MAGIC_64};
+use crate::read::{find_arch, FatArchEntry, FatReader};
+use crate::write::{read_prefix, MAXSECTALIGN, SIZEOF_FAT_ARCH_64};
+
+/// Mach-O fat binary reader over a seekable stream
+///
+/// Only the fat header and fat_arch table are read up front, slices are
+/// copied from the stream on demand.
+#[derive(Debug)]
+pub struct FatStreamReader<R> {
+    reader: R,
+    arches: Vec<FatArchEntry>,
+}
+
+impl<R: Read + Seek> FatStreamReader<R> {
+    /// Parse the fat header of a stream
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let arches = read_fat_arches(&mut reader)?.ok_or(Error::NotFatBinary)?;
+        Ok(Self { reader, arches })
+    }
+
+    /// Names of the architectures in this fat binary, in header order
+    ///
+    /// Unknown architectures are named after their cputype and cpusubtype.
+    pub fn arch_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.arches
+            .iter()
+            .map(|arch| display_arch_name(arch.cputype, arch.cpusubtype))
+    }
+
+    /// Copy the thin binary of an architecture into `writer`
+    ///
+    /// Returns the number of bytes copied, or [`Error::MissingArches`] if the
+    /// architecture is absent.
+    pub fn extract_to<W: Write + ?Sized>(
+        &mut self,
+        arch_name: &str,
+        writer: &mut W,
+    ) -> Result<u64, Error> {
+        let arch = *find_arch(&self.arches, arch_name)
+            .ok_or_else(|| Error::MissingArches(vec![arch_name.to_string()]))?;
+        self.reader.seek(SeekFrom::Start(arch.offset))?;
+        let copied = io::copy(&mut (&mut self.reader).take(arch.size), writer)?;
+        if copied != arch.size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "slice is truncated").into());
+        }
+        Ok(copied)
+    }
+
+    /// Get back the underlying stream
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Read the fat_arch table of a stream, or `None` if it isn't a fat binary
+pub(crate) fn read_fat_arches<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<Option<Vec<FatArchEntry>>, Error> {
+    let size = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0; SIZEOF_FAT_HEADER];
+    let len = read_prefix(reader, &mut header)?;
+    let table_size = match fat_table_size(&header[..len], size)? {
+        Some(table_size) => table_size,
+        None => return Ok(None),
+    };
+    let mut table = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    reader.take(table_size).read_to_end(&mut table)?;
+    let read_len = header_read_len(&table, size)?;
+    // Also read any hidden arm64 entries between the table and the first slice
+    reader
+        .take(read_len - table.len() as u64)
+        .read_to_end(&mut table)?;
+    let fat = FatReader::with_stream_len(&table, size)?;
+    Ok(Some(fat.arch_entries().to_vec()))
+}
+
+/// Bytes to read from the start of a stream of `size` bytes to get every fat_arch entry
+///
+/// `table` holds the fat header and the nfat_arch entries. Hidden entries
+/// follow them, so this reads up to the first slice, capped at the largest
+/// alignment padding a well-formed fat binary can have.
+fn header_read_len(table: &[u8], size: u64) -> Result<u64, Error> {
+    let first_offset = FatReader::with_stream_len(table, size)?
+        .arch_entries()
+        .iter()
+        .map(|arch| arch.offset)
+        .min()
+        .unwrap_or(0);
+    let table_size = table.len() as u64;
+    Ok(first_offset
+        .min(size)
+        .min(table_size + (1 << MAXSECTALIGN))
+        .max(table_size))
+}
+
+/// Size of the fat header and fat_arch table of a stream of `size` bytes
+///
+/// Returns `None` if `header` isn't a fat header, and
+/// [`Error::TooManyArches`] if the table doesn't fit in the stream.
+fn fat_table_size(header: &[u8], size: u64) -> Result<Option<u64>, Error> {
+    if header.len() < SIZEOF_FAT_HEADER {
+        return Ok(None);
+    }
+    let magic = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    if !is_fat_magic(magic) {
+        return Ok(None);
+    }
+    let nfat_arch = [header[4], header[5], header[6], header[7]];
+    let nfat_arch = if matches!(magic, FAT_CIGAM | FAT_CIGAM_64) {
+        u32::from_le_bytes(nfat_arch)
+    } else {
+        u32::from_be_bytes(nfat_arch)
+    };
+    let arch_size = if matches!(magic, FAT_MAGIC_64 | FAT_CIGAM_64) {
+        SIZEOF_FAT_ARCH_64
+    } else {
+        SIZEOF_FAT_ARCH
+    } as u64;
+    // Check before reading so a bogus nfat_arch can't make us buffer the stream
+    let table_size = SIZEOF_FAT_HEADER as u64 + nfat_arch as u64 * arch_size;
+    if table_size > size {
+        let max = size.saturating_sub(SIZEOF_FAT_HEADER as u64) / arch_size;
+        return Err(Error::TooManyArches {
+            count: nfat_arch,
+            max: u32::try_from(max).unwrap_or(u32::MAX),
+        });
+    }
+    Ok(Some(table_size))
+}
+
+/// Mach-O fat binary reader over an async seekable stream
+///
+/// See [`FatStreamReader`].
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncFatStreamReader<R> {
+    reader: R,
+    arches: Vec<FatArchEntry>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncFatStreamReader<tokio::fs::File> {
+    /// Open a fat binary file and parse its fat header
+    pub async fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        Self::new(tokio::fs::File::open(path).await?).await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncFatStreamReader<R> {
+    /// Parse the fat header of a stream
+    pub async fn new(mut reader: R) -> Result<Self, Error> {
+        let size = reader.seek(SeekFrom::End(0)).await?;
+        reader.seek(SeekFrom::Start(0)).await?;
+        let mut header = Vec::with_capacity(SIZEOF_FAT_HEADER);
+        (&mut reader)
+            .take(SIZEOF_FAT_HEADER as u64)
+            .read_to_end(&mut header)
+            .await?;
+        let table_size = fat_table_size(&header, size)?.ok_or(Error::NotFatBinary)?;
+        let mut table = Vec::new();
+        reader.seek(SeekFrom::Start(0)).await?;
+        (&mut reader)
+            .take(table_size)
+            .read_to_end(&mut table)
+            .await?;
+        let read_len = header_read_len(&table, size)?;
+        (&mut reader)
+            .take(read_len - table.len() as u64)
+            .read_to_end(&mut table)
+            .await?;
+        let arches = FatReader::with_stream_len(&table, size)?
+            .arch_entries()
+            .to_vec();
+        Ok(Self { reader, arches })
+    }
+
+    /// Names of the architectures in this fat binary, in header order
+    ///
+    /// Unknown architectures are named after their cputype and cpusubtype.
+    pub fn arch_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.arches
+            .iter()
+            .map(|arch| display_arch_name(arch.cputype, arch.cpusubtype))
+    }
+
+    /// Copy the thin binary of an architecture into `writer`
+    ///
+    /// See [`FatStreamReader::extract_to`].
+    pub async fn extract_to<W: AsyncWrite + Unpin + ?Sized>(
+        &mut self,
+        arch_name: &str,
+        writer: &mut W,
+    ) -> Result<u64, Error> {
+        let arch = *find_arch(&self.arches, arch_name)
+            .ok_or_else(|| Error::MissingArches(vec![arch_name.to_string()]))?;
+        self.reader.seek(SeekFrom::Start(arch.offset)).await?;
+        let copied = tokio::io::copy(&mut (&mut self.reader).take(arch.size), writer).await?;
+        if copied != arch.size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "slice is truncated").into());
+        }
+        Ok(copied)
+    }
+
+    /// Get back the underlying stream
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::FatStreamReader;
+    use crate::error::Error;
+    use crate::read::FatReader;
+
+    #[test]
+    fn test_fat_stream_reader() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let mut stream =
+            FatStreamReader::new(fs::File::open("tests/fixtures/hellofat").unwrap()).unwrap();
+        assert_eq!(
+            stream.arch_names().collect::<Vec<_>>(),
+            ["x86_64", "x86_64h", "arm64"]
+        );
+
+        let mut out = Vec::new();
+        let len = stream.extract_to("arm64", &mut out).unwrap();
+        assert_eq!(len, out.len() as u64);
+        assert_eq!(reader.extract("arm64"), Some(&out[..]));
+        assert!(matches!(
+            stream.extract_to("i386", &mut Vec::new()),
+            Err(Error::MissingArches(_))
+        ));
+
+        let thin = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert!(matches!(
+            FatStreamReader::new(Cursor::new(thin)),
+            Err(Error::NotFatBinary)
+        ));
+
+        let mut stream = FatStreamReader::new(Cursor::new(&buf[..buf.len() - 1])).unwrap();
+        assert!(stream.extract_to("arm64", &mut Vec::new()).is_err());
+
+        // Rejected before the table is read
+        let mut bad = buf.clone();
+        bad[4..8].copy_from_slice(&0x1000_0000u32.to_be_bytes());
+        let max = (buf.len() as u32 - 8) / 20;
+        assert!(matches!(
+            FatStreamReader::new(Cursor::new(bad)),
+            Err(Error::TooManyArches { count: 0x1000_0000, max: m }) if m == max
+        ));
+
+        // Unknown architectures are named after their cputype and cpusubtype
+        let mut fat = crate::FatWriter::new();
+        fat.add(crate::test_support::minimal_macho(0x1234, 5))
+            .unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let stream = FatStreamReader::new(Cursor::new(out)).unwrap();
+        assert_eq!(
+            stream.arch_names().collect::<Vec<_>>(),
+            ["cputype 4660 cpusubtype 5"]
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_fat_stream_reader() {
+        use super::AsyncFatStreamReader;
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let mut stream = AsyncFatStreamReader::open("tests/fixtures/hellofat")
+            .await
+            .unwrap();
+        assert_eq!(stream.arch_names().count(), 3);
+        let mut out = Vec::new();
+        stream.extract_to("x86_64h", &mut out).await.unwrap();
+        assert_eq!(reader.extract("x86_64h"), Some(&out[..]));
+
+        let thin = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert!(matches!(
+            AsyncFatStreamReader::new(Cursor::new(thin)).await,
+            Err(Error::NotFatBinary)
+        ));
+    }
+}
@@ -0,0 +1,52 @@
+//! Helpers for building Mach-O inputs in tests without checked-in binaries
+use goblin::mach::{
+    cputype::CPU_ARCH_ABI64,
+    header::{MH_MAGIC, MH_MAGIC_64, MH_OBJECT, SIZEOF_HEADER_64},
+};
+
+/// Build the smallest thin Mach-O object file accepted by [`FatWriter::add`](crate::FatWriter::add)
+///
+/// The result is a little-endian `MH_OBJECT` header without any load commands,
+/// using the 64-bit layout when `cpu_type` has `CPU_ARCH_ABI64` set.
+pub fn minimal_macho(cpu_type: u32, cpu_subtype: u32) -> Vec<u8> {
+    let magic = if cpu_type & CPU_ARCH_ABI64 != 0 {
+        MH_MAGIC_64
+    } else {
+        MH_MAGIC
+    };
+    // magic, cputype, cpusubtype, filetype, ncmds, sizeofcmds, flags
+    let fields = [magic, cpu_type, cpu_subtype, MH_OBJECT, 0, 0, 0];
+    let mut buf: Vec<u8> = fields.iter().flat_map(|x| x.to_le_bytes()).collect();
+    // goblin wants at least a 64-bit header worth of bytes even for 32-bit
+    // objects, so always pad to that size
+    buf.resize(SIZEOF_HEADER_64, 0);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::mach::cputype::{
+        CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_I386_ALL, CPU_SUBTYPE_X86_64_ALL, CPU_TYPE_ARM64,
+        CPU_TYPE_I386, CPU_TYPE_X86_64,
+    };
+
+    use super::minimal_macho;
+    use crate::{FatReader, FatWriter};
+
+    #[test]
+    fn test_minimal_macho() {
+        let mut fat = FatWriter::new();
+        fat.add(minimal_macho(CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL))
+            .unwrap();
+        fat.add(minimal_macho(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL))
+            .unwrap();
+        fat.add(minimal_macho(CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL))
+            .unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+
+        let reader = FatReader::new(&out).unwrap();
+        assert!(reader.covers(&["x86_64", "arm64", "i386"]).is_ok());
+        assert_eq!(reader.extract("i386").unwrap().len(), 32);
+    }
+}
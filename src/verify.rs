@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::arch::same_arch;
+use crate::read::FatArchEntry;
+
+/// A structural problem in a fat binary
+///
+/// Slices are identified by their index in the fat_arch table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Diagnostic {
+    /// The fat header declares no architectures
+    NoArches,
+    /// A slice starts inside the fat header or fat_arch table
+    OverlapsHeader { index: usize, offset: u64 },
+    /// Two slices share bytes
+    Overlap { first: usize, second: usize },
+    /// A slice starts past the end of the file
+    OffsetBeyondEof { index: usize, offset: u64 },
+    /// A slice ends past the end of the file
+    Truncated { index: usize, end: u64, len: u64 },
+    /// A slice offset is not a multiple of its declared alignment
+    Misaligned {
+        index: usize,
+        offset: u64,
+        align: u32,
+    },
+    /// Two slices have the same cputype and cpusubtype
+    DuplicateArch { first: usize, second: usize },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::NoArches => write!(f, "fat header declares no architectures"),
+            Diagnostic::OverlapsHeader { index, offset } => write!(
+                f,
+                "slice {} at offset {:#x} overlaps the fat header",
+                index, offset
+            ),
+            Diagnostic::Overlap { first, second } => {
+                write!(f, "slices {} and {} overlap", first, second)
+            }
+            Diagnostic::OffsetBeyondEof { index, offset } => write!(
+                f,
+                "slice {} starts at offset {:#x} past the end of the file",
+                index, offset
+            ),
+            Diagnostic::Truncated { index, end, len } => write!(
+                f,
+                "slice {} ends at offset {:#x} but the file is only {:#x} bytes",
+                index, end, len
+            ),
+            Diagnostic::Misaligned {
+                index,
+                offset,
+                align,
+            } => write!(
+                f,
+                "slice {} offset {:#x} is not aligned to 2^{}",
+                index, offset, align
+            ),
+            Diagnostic::DuplicateArch { first, second } => {
+                write!(
+                    f,
+                    "slices {} and {} have the same architecture",
+                    first, second
+                )
+            }
+        }
+    }
+}
+
+/// Check fat_arch entries against each other and the file length
+///
+/// `header_len` is the size of the fat header including the fat_arch table.
+pub(crate) fn check_entries(
+    entries: &[FatArchEntry],
+    header_len: u64,
+    file_len: u64,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if entries.is_empty() {
+        diagnostics.push(Diagnostic::NoArches);
+    }
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.offset < header_len {
+            diagnostics.push(Diagnostic::OverlapsHeader {
+                index,
+                offset: entry.offset,
+            });
+        }
+        let end = entry.offset.saturating_add(entry.size);
+        if entry.offset > file_len {
+            diagnostics.push(Diagnostic::OffsetBeyondEof {
+                index,
+                offset: entry.offset,
+            });
+        } else if end > file_len {
+            diagnostics.push(Diagnostic::Truncated {
+                index,
+                end,
+                len: file_len,
+            });
+        }
+        let aligned = 1u64
+            .checked_shl(entry.align)
+            .is_some_and(|align| entry.offset % align == 0);
+        if !aligned {
+            diagnostics.push(Diagnostic::Misaligned {
+                index,
+                offset: entry.offset,
+                align: entry.align,
+            });
+        }
+    }
+    for (first, a) in entries.iter().enumerate() {
+        for (second, b) in entries.iter().enumerate().skip(first + 1) {
+            let overlaps = a.size > 0
+                && b.size > 0
+                && a.offset < b.offset.saturating_add(b.size)
+                && b.offset < a.offset.saturating_add(a.size);
+            if overlaps {
+                diagnostics.push(Diagnostic::Overlap { first, second });
+            }
+            if same_arch((a.cputype, a.cpusubtype), (b.cputype, b.cpusubtype)) {
+                diagnostics.push(Diagnostic::DuplicateArch { first, second });
+            }
+        }
+    }
+    diagnostics
+}
@@ -1,11 +1,21 @@
 // Ported from https://github.com/randall77/makefat/blob/master/makefat.go
-#[cfg(unix)]
+#[cfg(all(unix, feature = "std-fs"))]
 use std::os::unix::fs::PermissionsExt;
 use std::{
-    cmp::Ordering,
-    fs::File,
-    io::{self, BufWriter, Write},
-    path::Path,
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    fmt,
+    io::{self, IoSlice, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+#[cfg(feature = "std-fs")]
+use std::{
+    fs::{self, File, OpenOptions},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
 };
 
 #[cfg(feature = "bitcode")]
@@ -16,44 +26,566 @@ use goblin::mach::cputype::{
     CPU_SUBTYPE_ARM_V7S, CPU_SUBTYPE_I386_ALL, CPU_SUBTYPE_POWERPC_ALL, CPU_SUBTYPE_X86_64_ALL,
     CPU_SUBTYPE_X86_64_H,
 };
+#[cfg(feature = "std-fs")]
+use goblin::mach::header::{MH_DYLIB, MH_EXECUTE};
 use goblin::{
     archive::Archive,
     mach::{
         cputype::{
-            get_arch_from_flag, get_arch_name_from_types, CpuSubType, CpuType, CPU_ARCH_ABI64,
-            CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_HPPA, CPU_TYPE_I386,
-            CPU_TYPE_I860, CPU_TYPE_MC680X0, CPU_TYPE_MC88000, CPU_TYPE_POWERPC,
-            CPU_TYPE_POWERPC64, CPU_TYPE_SPARC, CPU_TYPE_X86_64,
+            get_arch_from_flag, CpuSubType, CpuType, CPU_ARCH_ABI64, CPU_TYPE_ARM, CPU_TYPE_ARM64,
+            CPU_TYPE_ARM64_32, CPU_TYPE_HPPA, CPU_TYPE_I386, CPU_TYPE_I860, CPU_TYPE_MC680X0,
+            CPU_TYPE_MC88000, CPU_TYPE_POWERPC, CPU_TYPE_POWERPC64, CPU_TYPE_SPARC,
+            CPU_TYPE_X86_64,
         },
-        fat::{FAT_MAGIC, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
+        fat::{SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
+        header::{
+            MH_CIGAM, MH_CIGAM_64, MH_MAGIC, MH_MAGIC_64, MH_OBJECT, SIZEOF_HEADER_32,
+            SIZEOF_HEADER_64,
+        },
+        load_command::{LC_SEGMENT, LC_SEGMENT_64},
         Mach,
     },
     Object,
 };
 #[cfg(feature = "bitcode")]
 use llvm_bitcode::{bitcode::BitcodeElement, Bitcode};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+use crate::arch::{arch_name, display_arch_name, same_arch, ArchFamily};
+use crate::codesign::strip_code_signature;
 use crate::error::Error;
+#[cfg(feature = "digest")]
+use crate::hash::{DigestWriter, FatDigests};
+use crate::magic::{FAT_MAGIC, FAT_MAGIC_64};
+use crate::read::{FatArchEntry, FatReader};
+use crate::stream::read_fat_arches;
+use crate::verify::{check_entries, Diagnostic};
 
-const FAT_MAGIC_64: u32 = FAT_MAGIC + 1;
-const SIZEOF_FAT_ARCH_64: usize = 32;
+pub(crate) const SIZEOF_FAT_ARCH_64: usize = 32;
 
+/// Largest section alignment cctools accepts, as a power of 2
+pub(crate) const MAXSECTALIGN: u32 = 15;
 const LLVM_BITCODE_WRAPPER_MAGIC: u32 = 0x0B17C0DE;
 
+/// Padding source, large enough to align to 2^MAXSECTALIGN in one chunk
+static ZEROES: [u8; 1 << MAXSECTALIGN] = [0; 1 << MAXSECTALIGN];
+
+/// Largest buffer slices are copied from streams through
+const COPY_BUFFER_SIZE: u64 = 1 << 20;
+
+/// Size of the chunks streamed slices are copied to async writers in
+#[cfg(feature = "tokio")]
+const ASYNC_CHUNK_SIZE: usize = 1 << 20;
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+type SharedReader<'a> = Arc<Mutex<dyn ReadSeek + Send + 'a>>;
+
+#[cfg(feature = "tokio")]
+type SharedFile = Arc<Mutex<File>>;
+
+/// Reader shared by the slices streamed from it
+#[derive(Clone)]
+struct StreamSource<'a> {
+    reader: SharedReader<'a>,
+    /// The same reader if it's a file, which async writes read on the blocking thread pool
+    #[cfg(feature = "tokio")]
+    file: Option<SharedFile>,
+}
+
+/// Bytes of a slice, either in memory or read from a stream at write time
+enum SliceData<'a> {
+    Memory(Cow<'a, [u8]>),
+    /// A range of a buffer shared with other slices
+    Shared {
+        buffer: Arc<Vec<u8>>,
+        range: Range<usize>,
+    },
+    Stream {
+        source: StreamSource<'a>,
+        offset: u64,
+        size: u64,
+    },
+}
+
+impl<'a> SliceData<'a> {
+    fn len(&self) -> u64 {
+        match self {
+            SliceData::Memory(data) => data.len() as u64,
+            SliceData::Shared { range, .. } => range.len() as u64,
+            SliceData::Stream { size, .. } => *size,
+        }
+    }
+
+    /// The bytes of an in-memory slice
+    fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            SliceData::Memory(data) => Some(data),
+            SliceData::Shared { buffer, range } => Some(&buffer[range.clone()]),
+            SliceData::Stream { .. } => None,
+        }
+    }
+
+    /// Part of an in-memory slice, sharing rather than copying its bytes if possible
+    ///
+    /// Returns an empty slice if the range is out of bounds.
+    fn sub_slice(&self, offset: u64, size: u64) -> SliceData<'a> {
+        let len = self.as_bytes().map_or(0, <[u8]>::len);
+        let range = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(size).ok())
+            .and_then(|(start, size)| Some(start..start.checked_add(size)?))
+            .filter(|range| range.end <= len)
+            .unwrap_or(0..0);
+        match self {
+            SliceData::Memory(Cow::Borrowed(data)) => {
+                SliceData::Memory(Cow::Borrowed(&data[range]))
+            }
+            SliceData::Memory(Cow::Owned(data)) => {
+                SliceData::Memory(Cow::Owned(data[range].to_vec()))
+            }
+            SliceData::Shared {
+                buffer,
+                range: outer,
+            } => SliceData::Shared {
+                buffer: buffer.clone(),
+                range: outer.start + range.start..outer.start + range.end,
+            },
+            SliceData::Stream { .. } => SliceData::Memory(Cow::Borrowed(&[])),
+        }
+    }
+
+    fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            SliceData::Memory(data) => Ok(data.into_owned()),
+            SliceData::Shared { buffer, range } => Ok(buffer[range].to_vec()),
+            SliceData::Stream {
+                source,
+                offset,
+                size,
+            } => {
+                let mut buf = Vec::new();
+                read_stream(&source.reader, offset, size, &mut buf)?;
+                Ok(buf)
+            }
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            SliceData::Memory(data) => writer.write_all(data),
+            SliceData::Shared { buffer, range } => writer.write_all(&buffer[range.clone()]),
+            SliceData::Stream {
+                source,
+                offset,
+                size,
+            } => read_stream(&source.reader, *offset, *size, writer),
+        }
+    }
+}
+
+/// Like `io::copy`, but through a buffer large enough to keep big slices from being syscall-bound
+///
+/// The buffer is sized for `size` bytes, up to [`COPY_BUFFER_SIZE`].
+fn copy_large<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    size: u64,
+) -> io::Result<u64> {
+    let mut buf = vec![0; size.min(COPY_BUFFER_SIZE) as usize];
+    let mut copied = 0;
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buf[..len])?;
+        copied += len as u64;
+    }
+}
+
+/// `len` zero bytes as chunks of [`ZEROES`]
+fn zero_padding(len: usize) -> impl Iterator<Item = &'static [u8]> {
+    (0..len)
+        .step_by(ZEROES.len())
+        .map(move |start| &ZEROES[..(len - start).min(ZEROES.len())])
+}
+
+/// Write all of `bufs`, like the unstable `Write::write_all_vectored`
+fn write_all_vectored<W: Write + ?Sized>(
+    writer: &mut W,
+    mut bufs: &[IoSlice<'_>],
+) -> io::Result<()> {
+    loop {
+        while bufs.first().is_some_and(|buf| buf.is_empty()) {
+            bufs = &bufs[1..];
+        }
+        if bufs.is_empty() {
+            return Ok(());
+        }
+        let mut len = match writer.write_vectored(bufs) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        // Skip the buffers written in full and finish a partially written one
+        while let Some(buf) = bufs.first() {
+            if len < buf.len() {
+                if len > 0 {
+                    writer.write_all(&buf[len..])?;
+                    bufs = &bufs[1..];
+                }
+                break;
+            }
+            len -= buf.len();
+            bufs = &bufs[1..];
+        }
+    }
+}
+
+impl<'a> fmt::Debug for SliceData<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SliceData::Memory(data) => f.debug_tuple("Memory").field(&data.len()).finish(),
+            SliceData::Shared { range, .. } => f.debug_tuple("Shared").field(range).finish(),
+            SliceData::Stream { offset, size, .. } => f
+                .debug_struct("Stream")
+                .field("offset", offset)
+                .field("size", size)
+                .finish(),
+        }
+    }
+}
+
+/// Copy `size` bytes at `offset` of a shared reader into `writer`
+fn read_stream<W: Write + ?Sized>(
+    reader: &SharedReader,
+    offset: u64,
+    size: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut reader = reader.lock().unwrap_or_else(|err| err.into_inner());
+    reader.seek(SeekFrom::Start(offset))?;
+    let copied = copy_large(&mut (&mut *reader).take(size), writer, size)?;
+    if copied != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "slice is truncated",
+        ));
+    }
+    Ok(())
+}
+
+/// Copy `size` bytes at `offset` of a stream into an async writer, one chunk at a time
+#[cfg(feature = "tokio")]
+async fn write_stream_async<W: AsyncWrite + Unpin>(
+    source: &StreamSource<'_>,
+    offset: u64,
+    size: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    for start in (offset..offset + size).step_by(ASYNC_CHUNK_SIZE) {
+        let len = (offset + size - start).min(ASYNC_CHUNK_SIZE as u64);
+        let chunk = match &source.file {
+            Some(file) => {
+                let reader: SharedReader<'static> = file.clone();
+                tokio::task::spawn_blocking(move || {
+                    let mut chunk = Vec::with_capacity(len as usize);
+                    read_stream(&reader, start, len, &mut chunk).map(|()| chunk)
+                })
+                .await??
+            }
+            // Not 'static, so it can't be moved to the blocking thread pool
+            None => {
+                let mut chunk = Vec::with_capacity(len as usize);
+                read_stream(&source.reader, start, len, &mut chunk)?;
+                chunk
+            }
+        };
+        writer.write_all(&chunk).await?;
+    }
+    Ok(())
+}
+
+/// fat_arch entry a slice of a fat input was read from
+#[derive(Debug, Clone, Copy)]
+struct FatSource {
+    /// Index of the fat input, in the order added
+    input: usize,
+    /// Declared alignment bits
+    align: u32,
+}
+
 #[derive(Debug)]
-struct ThinArch {
-    data: Vec<u8>,
+struct ThinArch<'a> {
+    data: SliceData<'a>,
     cpu_type: u32,
     cpu_subtype: u32,
     align: i64,
+    /// Alignment bits declared by the fat binary this slice was read from
+    fat_align: Option<u32>,
+    /// Index of the fat input this slice was read from, in the order added
+    fat_input: Option<usize>,
+    /// Offset written to the fat_arch header instead of the real one
+    declared_offset: Option<u64>,
+    /// Mach-O filetype, `None` for archives and bitcode
+    filetype: Option<u32>,
+    /// Permission bits of the file this slice was added from
+    #[cfg_attr(not(feature = "std-fs"), allow(dead_code))]
+    source_mode: Option<u32>,
+}
+
+#[cfg(feature = "std-fs")]
+impl ThinArch<'static> {
+    /// Use this slice in a writer of a shorter lifetime
+    ///
+    /// Shared readers make [`FatWriter`] invariant, so this can't be a plain coercion.
+    fn reborrow<'a>(self) -> ThinArch<'a> {
+        let data = match self.data {
+            SliceData::Memory(data) => SliceData::Memory(data),
+            SliceData::Shared { buffer, range } => SliceData::Shared { buffer, range },
+            SliceData::Stream {
+                source,
+                offset,
+                size,
+            } => SliceData::Stream {
+                source: StreamSource {
+                    reader: source.reader,
+                    #[cfg(feature = "tokio")]
+                    file: source.file,
+                },
+                offset,
+                size,
+            },
+        };
+        ThinArch {
+            data,
+            cpu_type: self.cpu_type,
+            cpu_subtype: self.cpu_subtype,
+            align: self.align,
+            fat_align: self.fat_align,
+            fat_input: self.fat_input,
+            declared_offset: self.declared_offset,
+            filetype: self.filetype,
+            source_mode: self.source_mode,
+        }
+    }
+}
+
+/// Order of the slices in the written fat binary
+///
+/// Every slice is padded up to the alignment boundary, so the order can
+/// change the output file size by up to one alignment unit per slice.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum SortOrder {
+    /// Sort by alignment to save space, with arm64 slices placed last
+    #[default]
+    Alignment,
+    /// Sort exactly like cctools `lipo` does, to reproduce its output
+    ///
+    /// Like [`SortOrder::Alignment`], except that cpusubtypes are compared
+    /// as signed integers, so e.g. arm64e slices with capability bits come
+    /// before arm64, and alignment overrides are taken into account.
+    LipoCompatible,
+    /// Smallest slice first
+    BySizeAsc,
+    /// Largest slice first
+    BySizeDesc,
+    /// Keep the order the slices were added in
+    PreserveInsertion,
+    /// Sort with a comparison function, ties keep the order the slices were added in
+    Custom(fn(&SortKey, &SortKey) -> Ordering),
+}
+
+impl PartialEq for SortOrder {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SortOrder::Custom(a), SortOrder::Custom(b)) => *a as usize == *b as usize,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for SortOrder {}
+
+/// A slice as seen by [`SortOrder::Custom`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    /// Architecture name, `None` if the cpu type is unknown
+    pub arch_name: Option<&'static str>,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    /// Size of the slice
+    pub size: u64,
+    /// Alignment of the slice in the output, as a power of 2
+    pub align: u32,
+    /// Mach-O filetype, `None` for archives and bitcode
+    pub filetype: Option<u32>,
+    /// Position the slice was added at
+    pub index: usize,
+}
+
+/// The `cmp_qsort` ordering of cctools `lipo`
+fn lipo_order(a: &SortKey, b: &SortKey) -> Ordering {
+    // lipo subtracts the fields as signed ints
+    if a.cputype == b.cputype {
+        return (a.cpusubtype as i32)
+            .wrapping_sub(b.cpusubtype as i32)
+            .cmp(&0);
+    }
+    // arm64-family slices go after all others
+    if a.cputype == CPU_TYPE_ARM64 {
+        return Ordering::Greater;
+    }
+    if b.cputype == CPU_TYPE_ARM64 {
+        return Ordering::Less;
+    }
+    a.align.cmp(&b.align)
+}
+
+/// Fat header layout to write
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FatFormat {
+    /// Use fat32 unless a slice offset or size needs 64 bits
+    #[default]
+    Auto,
+    /// Always use fat32, failing if a slice doesn't fit
+    Fat32,
+    /// Always use fat64
+    Fat64,
+}
+
+/// What to do when adding an architecture that already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail with [`Error::DuplicatedArch`]
+    #[default]
+    Error,
+    /// Keep the existing slice and ignore the new one
+    Skip,
+    /// Replace the existing slice with the new one
+    Replace,
+}
+
+/// Options for [`FatWriter::with_options`]
+#[derive(Debug, Clone, Default)]
+pub struct FatWriterOptions {
+    format: FatFormat,
+    sort_order: SortOrder,
+    preserve_input_order: bool,
+    duplicate_policy: DuplicatePolicy,
+    /// Alignment bits by arch name
+    align: Vec<(String, u32)>,
+    file_mode: Option<u32>,
+    check_filetypes: bool,
+    hide_arm64: bool,
+    write_in_place: bool,
+    strip_code_signature: bool,
+    #[cfg(feature = "codesign")]
+    ad_hoc_sign: Option<String>,
+}
+
+impl FatWriterOptions {
+    /// Create the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the fat header layout
+    pub fn format(mut self, format: FatFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the order of the slices, see [`FatWriter::set_sort_order`]
+    pub fn sort_order(mut self, order: SortOrder) -> Self {
+        self.sort_order = order;
+        self
+    }
+
+    /// Keep the slice order of fat inputs, see [`FatWriter::preserve_input_order`]
+    pub fn preserve_input_order(mut self, preserve: bool) -> Self {
+        self.preserve_input_order = preserve;
+        self
+    }
+
+    /// Set what happens when adding an architecture that already exists
+    pub fn duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Override the alignment of an architecture, as a power of 2
+    ///
+    /// Writing fails if `align_bits` is above 15, the largest alignment
+    /// cctools accepts.
+    pub fn align(mut self, arch: &str, align_bits: u32) -> Self {
+        self.align.retain(|(name, _)| name != arch);
+        self.align.push((arch.to_string(), align_bits));
+        self
+    }
+
+    /// Set the output file permission bits, see [`FatWriter::set_file_mode`]
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Write files in place instead of atomically, see [`FatWriter::set_write_in_place`]
+    pub fn write_in_place(mut self, in_place: bool) -> Self {
+        self.write_in_place = in_place;
+        self
+    }
+
+    /// Hide arm64 slices from the fat header, see [`FatWriter::set_hide_arm64`]
+    pub fn hide_arm64(mut self, hide: bool) -> Self {
+        self.hide_arm64 = hide;
+        self
+    }
+
+    /// Require all Mach-O slices to share a filetype, see [`FatWriter::set_check_filetypes`]
+    pub fn check_filetypes(mut self, check: bool) -> Self {
+        self.check_filetypes = check;
+        self
+    }
+
+    /// Strip code signatures from added slices, see [`FatWriter::set_strip_code_signature`]
+    pub fn strip_code_signature(mut self, strip: bool) -> Self {
+        self.strip_code_signature = strip;
+        self
+    }
+
+    /// Ad-hoc sign added slices, see [`FatWriter::set_ad_hoc_sign`]
+    #[cfg(feature = "codesign")]
+    pub fn ad_hoc_sign(mut self, identifier: &str) -> Self {
+        self.ad_hoc_sign = Some(identifier.to_string());
+        self
+    }
 }
 
 /// Mach-O fat binary writer
+///
+/// Slices added with [`FatWriter::add_borrowed`] are borrowed for `'a` and
+/// only copied into the output by [`FatWriter::write_to`].
 #[derive(Debug)]
-pub struct FatWriter {
-    arches: Vec<ThinArch>,
-    max_align: i64,
+pub struct FatWriter<'a> {
+    arches: Vec<ThinArch<'a>>,
     is_fat64: bool,
+    header_gap: Vec<u8>,
+    /// Number of fat binaries added so far
+    fat_inputs: usize,
+    options: FatWriterOptions,
 }
 
 #[inline]
@@ -67,93 +599,489 @@ fn unpack_u32(buf: &[u8]) -> io::Result<u32> {
     Ok(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]))
 }
 
-impl FatWriter {
+impl<'a> Default for FatWriter<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> FatWriter<'a> {
     /// Create a new Mach-O fat binary writer
     pub fn new() -> Self {
         Self {
             arches: Vec::new(),
-            max_align: 0,
             is_fat64: false,
+            header_gap: Vec::new(),
+            fat_inputs: 0,
+            options: FatWriterOptions::default(),
+        }
+    }
+
+    /// Create a new Mach-O fat binary writer with options
+    pub fn with_options(options: FatWriterOptions) -> Self {
+        Self {
+            options,
+            ..Self::new()
+        }
+    }
+
+    /// Set the permission bits used by [`FatWriter::write_to_file`]
+    ///
+    /// By default the output gets the permission bits of every slice
+    /// combined, those of the source file for slices added with
+    /// [`FatWriter::add_path`] and `0o644` otherwise. Executables always get
+    /// the execute bits and dylibs keep those of their source file, any other
+    /// slice never makes the output executable. Setuid, setgid and sticky
+    /// bits are never copied.
+    pub fn set_file_mode(&mut self, mode: u32) {
+        self.options.file_mode = Some(mode);
+    }
+
+    /// Make [`FatWriter::write_to_file`] truncate and write the destination directly
+    ///
+    /// This is faster than the default atomic replacement but leaves a
+    /// partially written file behind if writing fails.
+    pub fn set_write_in_place(&mut self, in_place: bool) {
+        self.options.write_in_place = in_place;
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn file_mode(&self) -> u32 {
+        if let Some(mode) = self.options.file_mode {
+            return mode;
+        }
+        self.arches
+            .iter()
+            .map(|arch| {
+                let source_mode = arch.source_mode.map(|mode| mode & 0o777);
+                match arch.filetype {
+                    Some(MH_EXECUTE) => source_mode.map_or(0o755, |mode| mode | 0o111),
+                    Some(MH_DYLIB) => source_mode.unwrap_or(0o644),
+                    _ => source_mode.map_or(0o644, |mode| mode & !0o111),
+                }
+            })
+            .fold(0, |mode, arch_mode| mode | arch_mode)
+    }
+
+    /// Set what happens when adding an architecture that already exists
+    ///
+    /// This applies to every slice of a fat input as well.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.options.duplicate_policy = policy;
+    }
+
+    /// Require all Mach-O slices to share a filetype when writing
+    ///
+    /// Mixing e.g. `MH_EXECUTE` and `MH_DYLIB` slices produces a fat binary
+    /// Apple's tools reject. When enabled, [`FatWriter::write_to`] fails with
+    /// [`Error::MixedFiletypes`] instead. Archives and bitcode are not checked.
+    pub fn set_check_filetypes(&mut self, check: bool) {
+        self.options.check_filetypes = check;
+    }
+
+    fn check_filetypes(&self, arches: &[&ThinArch<'a>]) -> Result<(), Error> {
+        let filetypes: Vec<(String, u32)> = arches
+            .iter()
+            .filter_map(|arch| {
+                let name = display_arch_name(arch.cpu_type, arch.cpu_subtype);
+                Some((name, arch.filetype?))
+            })
+            .collect();
+        match filetypes.first() {
+            Some((_, first)) if filetypes.iter().any(|(_, filetype)| filetype != first) => {
+                Err(Error::MixedFiletypes(filetypes))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Hide arm64 slices from the fat header like `lipo -hideARM64`
+    ///
+    /// The arm64 fat_arch entries are written after the ones counted by
+    /// nfat_arch, so that loaders predating iOS 9 don't see them. At least
+    /// one slice of another cpu type is required.
+    pub fn set_hide_arm64(&mut self, hide: bool) {
+        self.options.hide_arm64 = hide;
+    }
+
+    /// Remove the code signature of thin Mach-O binaries as they're added
+    ///
+    /// The `LC_CODE_SIGNATURE` load command is removed and the signature
+    /// blob at the end of `__LINKEDIT` is dropped, like
+    /// `codesign --remove-signature`. Only affects slices added afterwards.
+    pub fn set_strip_code_signature(&mut self, strip: bool) {
+        self.options.strip_code_signature = strip;
+    }
+
+    /// Replace the code signature of thin Mach-O binaries with an ad-hoc one as they're added
+    ///
+    /// Slices get a SHA-256 code directory for `identifier`, like the
+    /// signature the linker adds, replacing any existing signature. Object
+    /// files are left alone. Only affects slices added afterwards.
+    #[cfg(feature = "codesign")]
+    pub fn set_ad_hoc_sign(&mut self, identifier: &str) {
+        self.options.ad_hoc_sign = Some(identifier.to_string());
+    }
+
+    /// Whether added Mach-O slices get their code signature stripped or replaced
+    fn rewrites_code_signature(&self) -> bool {
+        #[cfg(feature = "codesign")]
+        if self.options.ad_hoc_sign.is_some() {
+            return true;
+        }
+        self.options.strip_code_signature
+    }
+
+    /// Strip or replace the code signature of a thin Mach-O slice as configured
+    ///
+    /// Returns `None` if the slice is left unchanged.
+    fn rewrite_code_signature(
+        &self,
+        bytes: &[u8],
+        filetype: u32,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        #[cfg(feature = "codesign")]
+        if let Some(identifier) = &self.options.ad_hoc_sign {
+            if filetype != MH_OBJECT {
+                return crate::codesign::ad_hoc_sign(bytes, identifier).map(Some);
+            }
         }
+        if self.options.strip_code_signature && filetype != MH_OBJECT {
+            return strip_code_signature(bytes);
+        }
+        Ok(None)
+    }
+
+    /// Set the order of the slices in the written fat binary
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        self.options.sort_order = order;
+    }
+
+    /// Keep the slices of each fat input in the order they appear in it
+    ///
+    /// Slices are still sorted by the [`SortOrder`], then the slices of each
+    /// fat input are put back in their input order within the positions they
+    /// were sorted into. Rewriting a single fat binary therefore reproduces
+    /// its layout while thin inputs are sorted as usual. Use
+    /// [`SortOrder::PreserveInsertion`] to keep every slice in the order added.
+    pub fn preserve_input_order(&mut self, preserve: bool) {
+        self.options.preserve_input_order = preserve;
+    }
+
+    /// Set the bytes written between the fat_arch table and the first slice
+    ///
+    /// Use this with [`FatReader::header_gap_bytes`](crate::FatReader::header_gap_bytes)
+    /// to preserve non-zero header padding when rewriting a fat binary.
+    pub fn set_header_gap_bytes<T: Into<Vec<u8>>>(&mut self, bytes: T) {
+        self.header_gap = bytes.into();
     }
 
     /// Add a new thin Mach-O binary
     pub fn add<T: Into<Vec<u8>>>(&mut self, bytes: T) -> Result<(), Error> {
+        self.add_slice(SliceData::Memory(Cow::Owned(bytes.into())), None)
+    }
+
+    /// Add a new thin Mach-O binary from a file without loading it into memory
+    ///
+    /// See [`FatWriter::add_reader`].
+    #[cfg(feature = "std-fs")]
+    pub fn add_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let (added, mode) = Self::read_path(self.options.clone(), path.as_ref())?;
+        self.append(added, mode)
+    }
+
+    /// Add a new thin Mach-O binary from a file without blocking the executor
+    ///
+    /// The headers are read on the blocking thread pool, see
+    /// [`FatWriter::add_path`].
+    #[cfg(feature = "tokio")]
+    pub async fn add_path_async<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let options = self.options.clone();
+        let path = path.as_ref().to_path_buf();
+        let (added, mode) = tokio::task::spawn_blocking(move || Self::read_path(options, &path))
+            .await
+            .map_err(io::Error::from)??;
+        self.append(added, mode)
+    }
+
+    /// Read the slices of a file into a new writer, along with the file's mode
+    #[cfg(feature = "std-fs")]
+    fn read_path(
+        options: FatWriterOptions,
+        path: &Path,
+    ) -> Result<(FatWriter<'static>, Option<u32>), Error> {
+        let mut file = File::open(path)?;
+        let mode = source_mode(&file.metadata()?);
+        let arches = read_fat_arches(&mut file)?;
+        let size = file.seek(SeekFrom::End(0))?;
+        let file = Arc::new(Mutex::new(file));
+        let source = StreamSource {
+            #[cfg(feature = "tokio")]
+            file: Some(file.clone()),
+            reader: file,
+        };
+        let mut added = FatWriter::with_options(options);
+        added.add_source(source, arches, size)?;
+        Ok((added, mode))
+    }
+
+    /// Move the slices of `added` into this writer, recording the mode of the file they came from
+    #[cfg(feature = "std-fs")]
+    fn append(&mut self, added: FatWriter<'static>, mode: Option<u32>) -> Result<(), Error> {
+        self.is_fat64 |= added.is_fat64;
+        let first_input = self.fat_inputs;
+        self.fat_inputs += added.fat_inputs;
+        for arch in added.arches {
+            let mut arch = arch.reborrow();
+            arch.source_mode = mode;
+            arch.fat_input = arch.fat_input.map(|input| first_input + input);
+            self.push_arch(arch)?;
+        }
+        Ok(())
+    }
+
+    fn next_fat_input(&mut self) -> usize {
+        self.fat_inputs += 1;
+        self.fat_inputs - 1
+    }
+
+    /// Add a new thin Mach-O binary from a reader without loading it into memory
+    ///
+    /// Only the Mach-O headers are read up front, the slice data is copied
+    /// from the reader by [`FatWriter::write_to`]. Archives and LLVM bitcode
+    /// are read into memory as they need to be fully parsed.
+    pub fn add_reader<R: Read + Seek + Send + 'a>(&mut self, mut reader: R) -> Result<(), Error> {
+        let arches = read_fat_arches(&mut reader)?;
+        let size = reader.seek(SeekFrom::End(0))?;
+        let source = StreamSource {
+            reader: Arc::new(Mutex::new(reader)),
+            #[cfg(feature = "tokio")]
+            file: None,
+        };
+        self.add_source(source, arches, size)
+    }
+
+    /// Add the slices of a stream of `size` bytes, given its fat_arch entries if it's fat
+    fn add_source(
+        &mut self,
+        source: StreamSource<'a>,
+        arches: Option<Vec<FatArchEntry>>,
+        size: u64,
+    ) -> Result<(), Error> {
+        match arches {
+            Some(arches) => {
+                let input = self.next_fat_input();
+                for arch in arches {
+                    let fat = FatSource {
+                        input,
+                        align: arch.align,
+                    };
+                    self.add_stream(source.clone(), arch.offset, arch.size, Some(fat))?;
+                }
+                Ok(())
+            }
+            None => self.add_stream(source, 0, size, None),
+        }
+    }
+
+    fn add_stream(
+        &mut self,
+        source: StreamSource<'a>,
+        offset: u64,
+        size: u64,
+        fat: Option<FatSource>,
+    ) -> Result<(), Error> {
+        let reader = &source.reader;
+        let mut header = Vec::with_capacity(SIZEOF_HEADER_64);
+        read_stream(
+            reader,
+            offset,
+            size.min(SIZEOF_HEADER_64 as u64),
+            &mut header,
+        )?;
+        match parse_macho_header(&header) {
+            Some(_) if self.rewrites_code_signature() => {
+                let mut bytes = Vec::new();
+                read_stream(reader, offset, size, &mut bytes)?;
+                self.add_slice(SliceData::Memory(Cow::Owned(bytes)), fat)
+            }
+            Some((cpu_type, cpu_subtype, filetype)) => {
+                // Only the load commands are needed to compute the alignment
+                if let Some(end) = macho_load_commands_end(&header) {
+                    header.clear();
+                    read_stream(reader, offset, size.min(end as u64), &mut header)?;
+                }
+                let align = get_align(&header, cpu_type);
+                let data = SliceData::Stream {
+                    source,
+                    offset,
+                    size,
+                };
+                self.push_arch(ThinArch {
+                    data,
+                    cpu_type,
+                    cpu_subtype,
+                    align,
+                    fat_align: fat.map(|fat| fat.align),
+                    fat_input: fat.map(|fat| fat.input),
+                    declared_offset: None,
+                    filetype: Some(filetype),
+                    source_mode: None,
+                })
+            }
+            None => {
+                let mut bytes = Vec::new();
+                read_stream(reader, offset, size, &mut bytes)?;
+                self.add_slice(SliceData::Memory(Cow::Owned(bytes)), fat)
+            }
+        }
+    }
+
+    /// Add a slice with an explicit cputype, cpusubtype and alignment
+    ///
+    /// The bytes are not parsed, so this works for cpu types goblin doesn't
+    /// know about. `align_bits` is the alignment as a power of 2.
+    pub fn add_with_arch<T: Into<Vec<u8>>>(
+        &mut self,
+        bytes: T,
+        cpu_type: CpuType,
+        cpu_subtype: CpuSubType,
+        align_bits: u32,
+    ) -> Result<(), Error> {
+        if align_bits > MAXSECTALIGN {
+            return Err(Error::InvalidMachO(format!(
+                "alignment 2^{} is too large",
+                align_bits
+            )));
+        }
         let bytes = bytes.into();
-        match Object::parse(&bytes)? {
+        let filetype = parse_macho_header(&bytes).map(|(_, _, filetype)| filetype);
+        self.push_arch(ThinArch {
+            data: SliceData::Memory(Cow::Owned(bytes)),
+            cpu_type,
+            cpu_subtype,
+            align: 1 << align_bits,
+            fat_align: Some(align_bits),
+            fat_input: None,
+            declared_offset: None,
+            filetype,
+            source_mode: None,
+        })
+    }
+
+    /// Add a new thin Mach-O binary without copying it
+    ///
+    /// Slices of a fat input are borrowed as well.
+    pub fn add_borrowed(&mut self, bytes: &'a [u8]) -> Result<(), Error> {
+        self.add_slice(SliceData::Memory(Cow::Borrowed(bytes)), None)
+    }
+
+    /// Add a new thin Mach-O binary shared with the caller without copying it
+    ///
+    /// Slices of a fat input share the buffer as well.
+    pub fn add_shared(&mut self, bytes: Arc<Vec<u8>>) -> Result<(), Error> {
+        let range = 0..bytes.len();
+        self.add_slice(
+            SliceData::Shared {
+                buffer: bytes,
+                range,
+            },
+            None,
+        )
+    }
+
+    /// Add an in-memory slice
+    fn add_slice(&mut self, data: SliceData<'a>, fat: Option<FatSource>) -> Result<(), Error> {
+        let bytes = data.as_bytes().unwrap_or_default();
+        match Object::parse(bytes)? {
             Object::Mach(mach) => match mach {
-                Mach::Fat(fat) => {
-                    for arch in fat.arches()? {
-                        let buffer = arch.slice(&bytes);
-                        self.add(buffer.to_vec())?;
+                Mach::Fat(_) => {
+                    // FatReader also finds arm64 slices hidden past nfat_arch
+                    let arches = FatReader::new(bytes)?.arch_entries().to_vec();
+                    // Share an owned fat binary between its slices instead of copying them
+                    let data = match data {
+                        SliceData::Memory(Cow::Owned(buffer)) => SliceData::Shared {
+                            range: 0..buffer.len(),
+                            buffer: Arc::new(buffer),
+                        },
+                        data => data,
+                    };
+                    let input = self.next_fat_input();
+                    for arch in arches {
+                        // Keep the declared alignment rather than recomputing it
+                        let fat = FatSource {
+                            input,
+                            align: arch.align,
+                        };
+                        self.add_slice(data.sub_slice(arch.offset, arch.size), Some(fat))?;
                     }
                 }
                 Mach::Binary(obj) => {
                     let header = obj.header;
                     let cpu_type = header.cputype;
                     let cpu_subtype = header.cpusubtype;
-                    // Check if this architecture already exists
-                    if self
-                        .arches
-                        .iter()
-                        .find(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
-                        .is_some()
-                    {
-                        let arch =
-                            get_arch_name_from_types(cpu_type, cpu_subtype).unwrap_or("unknown");
-                        return Err(Error::DuplicatedArch(arch.to_string()));
-                    }
                     if header.magic == FAT_MAGIC_64 {
                         self.is_fat64 = true;
                     }
-                    let align = get_align_from_cpu_types(cpu_type, cpu_subtype);
-                    if align > self.max_align {
-                        self.max_align = align;
-                    }
-                    let thin = ThinArch {
-                        data: bytes,
+                    let (data, align) = match self.rewrite_code_signature(bytes, header.filetype)? {
+                        Some(bytes) => {
+                            let align = get_align(&bytes, cpu_type);
+                            (SliceData::Memory(Cow::Owned(bytes)), align)
+                        }
+                        None => {
+                            let align = get_align(bytes, cpu_type);
+                            (data, align)
+                        }
+                    };
+                    self.push_arch(ThinArch {
+                        data,
                         cpu_type,
                         cpu_subtype,
                         align,
-                    };
-                    self.arches.push(thin);
+                        fat_align: fat.map(|fat| fat.align),
+                        fat_input: fat.map(|fat| fat.input),
+                        declared_offset: None,
+                        filetype: Some(header.filetype),
+                        source_mode: None,
+                    })?;
                 }
             },
             Object::Archive(ar) => {
-                let (cpu_type, cpu_subtype) = self.check_archive(&bytes, &ar)?;
+                let (cpu_type, cpu_subtype) = self.check_archive(bytes, &ar)?;
                 let align = if cpu_type & CPU_ARCH_ABI64 != 0 {
                     8 /* alignof(u64) */
                 } else {
                     4 /* alignof(u32) */
                 };
-                if align > self.max_align {
-                    self.max_align = align;
-                }
-                let thin = ThinArch {
-                    data: bytes,
+                self.push_arch(ThinArch {
+                    data,
                     cpu_type,
                     cpu_subtype,
                     align,
-                };
-                self.arches.push(thin);
+                    fat_align: fat.map(|fat| fat.align),
+                    fat_input: fat.map(|fat| fat.input),
+                    declared_offset: None,
+                    filetype: None,
+                    source_mode: None,
+                })?;
             }
             Object::Unknown(_) => {
-                let magic = unpack_u32(&bytes)?;
+                let magic = unpack_u32(bytes)?;
                 if magic == LLVM_BITCODE_WRAPPER_MAGIC {
                     #[cfg(feature = "bitcode")]
                     {
-                        let (cpu_type, cpu_subtype) = self.get_arch_from_bitcode(&bytes)?;
-                        let align = 1;
-                        if align > self.max_align {
-                            self.max_align = align;
-                        }
-                        let thin = ThinArch {
-                            data: bytes,
+                        let (cpu_type, cpu_subtype) = self.get_arch_from_bitcode(bytes)?;
+                        self.push_arch(ThinArch {
+                            data,
                             cpu_type,
                             cpu_subtype,
-                            align,
-                        };
-                        self.arches.push(thin);
+                            align: 1,
+                            fat_align: fat.map(|fat| fat.align),
+                            fat_input: fat.map(|fat| fat.input),
+                            declared_offset: None,
+                            filetype: None,
+                            source_mode: None,
+                        })?;
                     }
 
                     #[cfg(not(feature = "bitcode"))]
@@ -166,22 +1094,103 @@ impl FatWriter {
             }
             _ => return Err(Error::InvalidMachO("input is not a macho file".to_string())),
         }
-        // Sort the files by alignment to save space in ouput
-        self.arches.sort_by(|a, b| {
-            if a.cpu_type == b.cpu_type {
-                // if cpu types match, sort by cpu subtype
-                return a.cpu_subtype.cmp(&b.cpu_subtype);
+        Ok(())
+    }
+
+    fn push_arch(&mut self, thin: ThinArch<'a>) -> Result<(), Error> {
+        // Check if this architecture already exists
+        if let Some(index) = self.arches.iter().position(|arch| {
+            same_arch(
+                (arch.cpu_type, arch.cpu_subtype),
+                (thin.cpu_type, thin.cpu_subtype),
+            )
+        }) {
+            match self.options.duplicate_policy {
+                DuplicatePolicy::Error => {
+                    let arch = display_arch_name(thin.cpu_type, thin.cpu_subtype);
+                    return Err(Error::DuplicatedArch(arch));
+                }
+                DuplicatePolicy::Skip => return Ok(()),
+                DuplicatePolicy::Replace => {
+                    self.arches[index] = thin;
+                    return Ok(());
+                }
             }
-            // force arm64-family to follow after all other slices
-            if a.cpu_type == CPU_TYPE_ARM64 {
-                return Ordering::Greater;
+        }
+        self.arches.push(thin);
+        Ok(())
+    }
+
+    /// Slices in the order they will be written
+    fn sorted_arches(&self) -> Vec<&ThinArch<'a>> {
+        let mut arches: Vec<&ThinArch<'a>> = self.arches.iter().collect();
+        match self.options.sort_order {
+            SortOrder::Alignment => {
+                // Sort the files by alignment to save space in ouput
+                arches.sort_by(|a, b| {
+                    if a.cpu_type == b.cpu_type {
+                        // if cpu types match, sort by cpu subtype
+                        return a.cpu_subtype.cmp(&b.cpu_subtype);
+                    }
+                    // force arm64-family to follow after all other slices
+                    if a.cpu_type == CPU_TYPE_ARM64 {
+                        return Ordering::Greater;
+                    }
+                    if b.cpu_type == CPU_TYPE_ARM64 {
+                        return Ordering::Less;
+                    }
+                    a.align.cmp(&b.align)
+                });
             }
-            if b.cpu_type == CPU_TYPE_ARM64 {
-                return Ordering::Less;
+            SortOrder::LipoCompatible => self.sort_by_key_fn(&mut arches, lipo_order),
+            SortOrder::BySizeAsc => arches.sort_by_key(|arch| arch.data.len()),
+            SortOrder::BySizeDesc => arches.sort_by_key(|arch| Reverse(arch.data.len())),
+            SortOrder::PreserveInsertion => {}
+            SortOrder::Custom(compare) => self.sort_by_key_fn(&mut arches, compare),
+        }
+        if self.options.preserve_input_order {
+            for input in 0..self.fat_inputs {
+                let from_input = |arch: &&ThinArch| arch.fat_input == Some(input);
+                let positions: Vec<usize> = (0..arches.len())
+                    .filter(|&index| from_input(&arches[index]))
+                    .collect();
+                // self.arches is in the order the slices were added
+                let input_order = self.arches.iter().filter(from_input);
+                for (position, arch) in positions.into_iter().zip(input_order) {
+                    arches[position] = arch;
+                }
             }
-            a.align.cmp(&b.align)
-        });
-        Ok(())
+        }
+        if self.options.hide_arm64 {
+            // Hidden entries must follow all the counted ones
+            arches.sort_by_key(|arch| arch.cpu_type == CPU_TYPE_ARM64);
+        }
+        arches
+    }
+
+    fn sort_by_key_fn(
+        &self,
+        arches: &mut Vec<&ThinArch<'a>>,
+        compare: fn(&SortKey, &SortKey) -> Ordering,
+    ) {
+        let mut keyed: Vec<_> = arches
+            .drain(..)
+            .enumerate()
+            .map(|(index, arch)| {
+                let key = SortKey {
+                    arch_name: arch_name(arch.cpu_type, arch.cpu_subtype),
+                    cputype: arch.cpu_type,
+                    cpusubtype: arch.cpu_subtype,
+                    size: arch.data.len(),
+                    align: self.align_bits(arch),
+                    filetype: arch.filetype,
+                    index,
+                };
+                (key, arch)
+            })
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| compare(a, b));
+        arches.extend(keyed.into_iter().map(|(_, arch)| arch));
     }
 
     #[cfg(feature = "bitcode")]
@@ -211,7 +1220,7 @@ impl FatWriter {
                 String::from_utf8(fields).ok()
             });
         if let Some(triple) = target_triple {
-            if let Some(triple) = triple.splitn(2, "-").next() {
+            if let Some(triple) = triple.split('-').next() {
                 return Ok(match triple {
                     "i686" | "i386" => (CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL),
                     "x86_64" => (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
@@ -240,77 +1249,360 @@ impl FatWriter {
         Err(Error::InvalidMachO("input is not a macho file".to_string()))
     }
 
+    /// Get the architecture of a static archive, checking all Mach-O members agree
     fn check_archive(&self, buffer: &[u8], ar: &Archive) -> Result<(u32, u32), Error> {
+        let mut arch: Option<(&str, u32, u32)> = None;
         for member in ar.members() {
             let bytes = ar.extract(member, buffer)?;
-            match Object::parse(bytes)? {
-                Object::Mach(mach) => match mach {
-                    Mach::Binary(obj) => {
-                        return Ok((obj.header.cputype, obj.header.cpusubtype));
+            if let Object::Mach(Mach::Binary(obj)) = Object::parse(bytes)? {
+                let (cpu_type, cpu_subtype) = (obj.header.cputype, obj.header.cpusubtype);
+                match arch {
+                    None => arch = Some((member, cpu_type, cpu_subtype)),
+                    Some((first, first_type, first_subtype))
+                        if !same_arch((first_type, first_subtype), (cpu_type, cpu_subtype)) =>
+                    {
+                        return Err(Error::InvalidMachO(format!(
+                            "archive member {} is {} but {} is {}",
+                            member,
+                            display_arch_name(cpu_type, cpu_subtype),
+                            first,
+                            display_arch_name(first_type, first_subtype)
+                        )));
                     }
-                    Mach::Fat(_) => {}
-                },
-                _ => {}
+                    Some(_) => {}
+                }
             }
         }
-        Err(Error::InvalidMachO(
-            "No Mach-O objects found in archivec".to_string(),
-        ))
+        arch.map(|(_, cpu_type, cpu_subtype)| (cpu_type, cpu_subtype))
+            .ok_or_else(|| Error::InvalidMachO("No Mach-O objects found in archive".to_string()))
     }
 
     /// Remove an architecture
+    ///
+    /// Returns `None` if the architecture doesn't exist, or if it was added
+    /// from a reader that fails to read it back. See [`FatWriter::try_remove`]
+    /// to tell these apart.
     pub fn remove(&mut self, arch: &str) -> Option<Vec<u8>> {
-        if let Some((cpu_type, cpu_subtype)) = get_arch_from_flag(arch) {
-            if let Some(index) = self
-                .arches
-                .iter()
-                .position(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
-            {
-                return Some(self.arches.remove(index).data);
-            }
-        }
-        None
+        self.try_remove(arch).ok().flatten()
     }
 
-    /// Check whether a certain architecture exists in this fat binary
-    pub fn exists(&self, arch: &str) -> bool {
-        if let Some((cpu_type, cpu_subtype)) = get_arch_from_flag(arch) {
-            return self
-                .arches
-                .iter()
-                .find(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
-                .is_some();
+    /// Remove an architecture, returning an error if its slice can't be read back
+    ///
+    /// The architecture is removed even if reading its slice fails.
+    pub fn try_remove(&mut self, arch: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.position(arch) {
+            Some(index) => Ok(Some(self.arches.remove(index).data.into_bytes()?)),
+            None => Ok(None),
         }
-        false
     }
 
-    /// Write Mach-O fat binary into the writer
-    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
-        if self.arches.is_empty() {
-            return Ok(());
+    /// Remove every architecture of a family, returning the names of those removed
+    ///
+    /// See [`FatReader::extract_family`] for how family names match.
+    pub fn remove_family(&mut self, family: &str) -> Vec<String> {
+        let Some(family) = ArchFamily::from_name(family) else {
+            return Vec::new();
+        };
+        let mut removed = Vec::new();
+        self.arches.retain(|arch| {
+            let matches = family.contains(arch.cpu_type, arch.cpu_subtype);
+            if matches {
+                removed.push(display_arch_name(arch.cpu_type, arch.cpu_subtype));
+            }
+            !matches
+        });
+        removed
+    }
+
+    /// Remove an architecture by cputype and cpusubtype
+    ///
+    /// Unlike [`FatWriter::remove`] this works for architectures without a name.
+    /// Like [`FatWriter::try_remove`] it fails if the slice can't be read back.
+    pub fn remove_by_type(
+        &mut self,
+        cpu_type: CpuType,
+        cpu_subtype: CpuSubType,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        match self.position_by_type(cpu_type, cpu_subtype) {
+            Some(index) => Ok(Some(self.arches.remove(index).data.into_bytes()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the thin binary of an architecture, returning the previous one
+    ///
+    /// The new binary must be a thin binary of the named architecture. It
+    /// takes the place of the previous slice, or is added if there was none.
+    pub fn replace<T: Into<Vec<u8>>>(
+        &mut self,
+        arch: &str,
+        bytes: T,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (cpu_type, cpu_subtype) = get_arch_from_flag(arch)
+            .ok_or_else(|| Error::InvalidMachO(format!("unknown architecture {}", arch)))?;
+        // Strip or re-sign the new slice like `add` does
+        let mut thin: FatWriter<'a> = FatWriter::with_options(self.options.clone());
+        thin.add(bytes)?;
+        let new = match (thin.arches.pop(), thin.arches.is_empty()) {
+            (Some(new), true) => new,
+            _ => {
+                return Err(Error::InvalidMachO(
+                    "input is not a thin binary".to_string(),
+                ))
+            }
+        };
+        if !same_arch((new.cpu_type, new.cpu_subtype), (cpu_type, cpu_subtype)) {
+            return Err(Error::ArchMismatch {
+                expected: arch.to_string(),
+                found: display_arch_name(new.cpu_type, new.cpu_subtype),
+            });
+        }
+        match self.position(arch) {
+            Some(index) => {
+                let old = std::mem::replace(&mut self.arches[index], new);
+                Ok(Some(old.data.into_bytes()?))
+            }
+            None => {
+                self.push_arch(new)?;
+                Ok(None)
+            }
         }
-        // Check whether we're doing fat32 or fat64
-        let is_fat64 =
-            if self.is_fat64 || self.arches.last().unwrap().data.len() as i64 >= 1i64 << 32 {
+    }
+
+    /// Names of the architectures in the order they will be written, like `lipo -archs`
+    ///
+    /// Unknown architectures are named after their cputype and cpusubtype.
+    pub fn archs(&self) -> Vec<String> {
+        self.sorted_arches()
+            .iter()
+            .map(|arch| display_arch_name(arch.cpu_type, arch.cpu_subtype))
+            .collect()
+    }
+
+    /// Check whether a certain architecture exists in this fat binary
+    pub fn exists(&self, arch: &str) -> bool {
+        self.position(arch).is_some()
+    }
+
+    /// Check whether an architecture exists by cputype and cpusubtype
+    pub fn exists_by_type(&self, cpu_type: CpuType, cpu_subtype: CpuSubType) -> bool {
+        self.position_by_type(cpu_type, cpu_subtype).is_some()
+    }
+
+    fn position(&self, arch: &str) -> Option<usize> {
+        let (cpu_type, cpu_subtype) = get_arch_from_flag(arch)?;
+        self.position_by_type(cpu_type, cpu_subtype)
+    }
+
+    fn position_by_type(&self, cpu_type: CpuType, cpu_subtype: CpuSubType) -> Option<usize> {
+        self.arches
+            .iter()
+            .position(|arch| same_arch((arch.cpu_type, arch.cpu_subtype), (cpu_type, cpu_subtype)))
+    }
+
+    /// Override the offset written to an architecture's fat_arch header
+    ///
+    /// The slice data is still placed at its computed offset, so the output
+    /// is deliberately malformed. This is only meant for testing how other
+    /// tools cope with bad fat binaries. Returns `false` if the architecture
+    /// doesn't exist.
+    pub fn set_declared_offset(&mut self, arch: &str, offset: u64) -> bool {
+        match self.position(arch) {
+            Some(index) => {
+                self.arches[index].declared_offset = Some(offset);
                 true
-            } else {
-                false
-            };
-        let align = self.max_align;
+            }
+            None => false,
+        }
+    }
+
+    /// Compute the offset and alignment bits of each slice
+    fn layout(&self, arches: &[&ThinArch<'a>], is_fat64: bool) -> (Vec<i64>, Vec<u32>) {
         let mut total_offset = SIZEOF_FAT_HEADER as i64;
         if is_fat64 {
-            total_offset += self.arches.len() as i64 * SIZEOF_FAT_ARCH_64 as i64;
+            total_offset += arches.len() as i64 * SIZEOF_FAT_ARCH_64 as i64;
         // narches * size of fat_arch_64
         } else {
-            total_offset += self.arches.len() as i64 * SIZEOF_FAT_ARCH as i64; // narches * size of fat_arch
+            total_offset += arches.len() as i64 * SIZEOF_FAT_ARCH as i64; // narches * size of fat_arch
         }
-        let mut arch_offsets = Vec::with_capacity(self.arches.len());
-        for arch in &self.arches {
+        total_offset += self.header_gap.len() as i64;
+        let mut arch_offsets = Vec::with_capacity(arches.len());
+        let mut arch_align_bits = Vec::with_capacity(arches.len());
+        for arch in arches {
+            let align_bits = self.align_bits(arch);
+            let align = 1i64 << align_bits;
             // Round up to multiple of align
             total_offset = (total_offset + align - 1) / align * align;
             arch_offsets.push(total_offset);
+            arch_align_bits.push(align_bits);
             total_offset += arch.data.len() as i64;
         }
+        (arch_offsets, arch_align_bits)
+    }
+
+    /// Alignment of a slice in the output, as a power of 2
+    fn align_bits(&self, arch: &ThinArch<'a>) -> u32 {
+        let align_override = self
+            .options
+            .align
+            .iter()
+            .find(|(name, _)| {
+                get_arch_from_flag(name)
+                    .is_some_and(|flag| same_arch(flag, (arch.cpu_type, arch.cpu_subtype)))
+            })
+            .map(|(_, bits)| *bits);
+        // Slices read from a fat binary keep their declared alignment,
+        // others use their own like lipo
+        align_override
+            .or(arch.fat_align)
+            .unwrap_or_else(|| arch.align.trailing_zeros())
+    }
+
+    /// Reject alignment overrides that `layout` can't pad to
+    fn check_align_overrides(&self) -> Result<(), Error> {
+        match self
+            .options
+            .align
+            .iter()
+            .find(|(_, align_bits)| *align_bits > MAXSECTALIGN)
+        {
+            Some((arch, align_bits)) => Err(Error::InvalidMachO(format!(
+                "alignment 2^{} of {} is too large",
+                align_bits, arch
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Check whether we're doing fat32 or fat64
+    fn use_fat64(&self, arches: &[&ThinArch<'a>]) -> Result<bool, Error> {
+        let (fat32_offsets, _) = self.layout(arches, false);
+        let fat32_overflow = arches
+            .iter()
+            .zip(&fat32_offsets)
+            .find(|(arch, offset)| **offset >= 1i64 << 32 || arch.data.len() >= 1u64 << 32)
+            .map(|(arch, _)| arch);
+        match self.options.format {
+            FatFormat::Auto => Ok(self.is_fat64 || fat32_overflow.is_some()),
+            FatFormat::Fat32 => match fat32_overflow {
+                Some(arch) => {
+                    let name = display_arch_name(arch.cpu_type, arch.cpu_subtype);
+                    Err(Error::Fat32Overflow(name))
+                }
+                None => Ok(false),
+            },
+            FatFormat::Fat64 => Ok(true),
+        }
+    }
+
+    /// Check the fat binary that would be written for structural problems
+    ///
+    /// Returns an empty list if [`FatWriter::write_to`] would produce a
+    /// well-formed fat binary.
+    pub fn validate(&self) -> Result<Vec<Diagnostic>, Error> {
+        self.check_align_overrides()?;
+        let arches = self.sorted_arches();
+        let is_fat64 = self.use_fat64(&arches)?;
+        let (arch_offsets, arch_align_bits) = self.layout(&arches, is_fat64);
+        let arch_size = if is_fat64 {
+            SIZEOF_FAT_ARCH_64
+        } else {
+            SIZEOF_FAT_ARCH
+        };
+        let header_len = SIZEOF_FAT_HEADER + arches.len() * arch_size;
+        let file_len = arches
+            .iter()
+            .zip(&arch_offsets)
+            .map(|(arch, offset)| *offset as u64 + arch.data.len())
+            .max()
+            .unwrap_or(header_len as u64);
+        let entries: Vec<FatArchEntry> = arches
+            .iter()
+            .zip(arch_offsets)
+            .zip(arch_align_bits)
+            .map(|((arch, offset), align)| FatArchEntry {
+                cputype: arch.cpu_type,
+                cpusubtype: arch.cpu_subtype,
+                offset: arch.declared_offset.unwrap_or(offset as u64),
+                size: arch.data.len(),
+                align,
+                hidden: self.options.hide_arm64 && arch.cpu_type == CPU_TYPE_ARM64,
+            })
+            .collect();
+        Ok(check_entries(&entries, header_len as u64, file_len))
+    }
+
+    /// Write Mach-O fat binary into the writer
+    ///
+    /// Returns the number of bytes written, or [`Error::NoArches`] if no
+    /// architectures were added.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<u64, Error> {
+        let (arches, header, arch_offsets) = self.prepare()?;
+        // In-memory slices and their padding are gathered into vectored
+        // writes, only slices read from streams interrupt them
+        let mut pending = vec![IoSlice::new(&header)];
+        let mut offset = header.len() as i64;
+        for (arch, arch_offset) in arches.iter().zip(arch_offsets) {
+            if offset < arch_offset {
+                for zeroes in zero_padding((arch_offset - offset) as usize) {
+                    pending.push(IoSlice::new(zeroes));
+                }
+                offset = arch_offset;
+            }
+            match arch.data.as_bytes() {
+                Some(bytes) => pending.push(IoSlice::new(bytes)),
+                None => {
+                    write_all_vectored(writer, &pending)?;
+                    pending.clear();
+                    arch.data.write_to(writer)?;
+                }
+            }
+            offset += arch.data.len() as i64;
+        }
+        write_all_vectored(writer, &pending)?;
+        Ok(offset as u64)
+    }
+
+    /// SHA-256 digest of every thin binary and of the fat binary [`FatWriter::write_to`] writes
+    ///
+    /// Slices are listed in output order. Slices added with
+    /// [`FatWriter::add_reader`] are read twice.
+    #[cfg(feature = "digest")]
+    pub fn digests(&self) -> Result<FatDigests, Error> {
+        self.digests_with::<sha2::Sha256>()
+    }
+
+    /// Digests like [`FatWriter::digests`], using hasher `D`
+    #[cfg(feature = "digest")]
+    pub fn digests_with<D: digest::Digest>(&self) -> Result<FatDigests, Error> {
+        let mut fat = DigestWriter::<D>::new();
+        self.write_to(&mut fat)?;
+        let mut slices = Vec::with_capacity(self.arches.len());
+        for arch in self.sorted_arches() {
+            let name = display_arch_name(arch.cpu_type, arch.cpu_subtype);
+            let mut digest = DigestWriter::<D>::new();
+            arch.data.write_to(&mut digest)?;
+            slices.push((name, digest.finalize()));
+        }
+        Ok(FatDigests {
+            slices,
+            fat: fat.finalize(),
+        })
+    }
+
+    /// Lay out the slices and build the fat header, including the header gap
+    #[allow(clippy::type_complexity)]
+    fn prepare(&self) -> Result<(Vec<&ThinArch<'a>>, Vec<u8>, Vec<i64>), Error> {
+        if self.arches.is_empty() {
+            return Err(Error::NoArches);
+        }
+        self.check_align_overrides()?;
+        let arches = self.sorted_arches();
+        if self.options.check_filetypes {
+            self.check_filetypes(&arches)?;
+        }
+        let is_fat64 = self.use_fat64(&arches)?;
+        let (arch_offsets, arch_align_bits) = self.layout(&arches, is_fat64);
         let mut hdr = Vec::with_capacity(12);
         // Build a fat_header
         if is_fat64 {
@@ -318,18 +1610,33 @@ impl FatWriter {
         } else {
             hdr.push(FAT_MAGIC);
         }
-        hdr.push(self.arches.len() as u32);
-        // Compute the max alignment bits
-        let align_bits = (align as f32).log2() as u32;
+        let nfat_arch = if self.options.hide_arm64 {
+            let visible = arches
+                .iter()
+                .filter(|arch| arch.cpu_type != CPU_TYPE_ARM64)
+                .count();
+            if visible == 0 {
+                return Err(Error::InvalidMachO(
+                    "hiding arm64 requires a slice of another cpu type".to_string(),
+                ));
+            }
+            visible
+        } else {
+            arches.len()
+        };
+        hdr.push(nfat_arch as u32);
         // Build a fat_arch for each arch
-        for (arch, arch_offset) in self.arches.iter().zip(arch_offsets.iter()) {
+        for ((arch, arch_offset), align_bits) in
+            arches.iter().zip(arch_offsets.iter()).zip(arch_align_bits)
+        {
             hdr.push(arch.cpu_type);
             hdr.push(arch.cpu_subtype);
+            let arch_offset = arch.declared_offset.unwrap_or(*arch_offset as u64);
             if is_fat64 {
                 // Big Endian
                 hdr.push((arch_offset >> 32) as u32);
             }
-            hdr.push(*arch_offset as u32);
+            hdr.push(arch_offset as u32);
             if is_fat64 {
                 hdr.push((arch.data.len() >> 32) as u32);
             }
@@ -340,64 +1647,367 @@ impl FatWriter {
                 hdr.push(0);
             }
         }
-        // Write header
         // Note that the fat binary header is big-endian, regardless of the
         // endianness of the contained files.
-        for i in &hdr {
-            writer.write_all(&i.to_be_bytes())?;
-        }
-        let mut offset = 4 * hdr.len() as i64;
-        // Write each arch
-        for (arch, arch_offset) in self.arches.iter().zip(arch_offsets) {
+        let mut header: Vec<u8> = hdr.iter().flat_map(|i| i.to_be_bytes()).collect();
+        header.extend_from_slice(&self.header_gap);
+        Ok((arches, header, arch_offsets))
+    }
+
+    /// Write Mach-O fat binary into an async writer
+    ///
+    /// Slices added from a path are read on the blocking thread pool, those
+    /// added with [`FatWriter::add_reader`] are read synchronously. Either
+    /// way they are copied in chunks rather than read into memory whole.
+    #[cfg(feature = "tokio")]
+    pub async fn write_to_async<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+    ) -> Result<u64, Error> {
+        let (arches, header, arch_offsets) = self.prepare()?;
+        writer.write_all(&header).await?;
+        let mut offset = header.len() as i64;
+        for (arch, arch_offset) in arches.iter().zip(arch_offsets) {
             if offset < arch_offset {
-                writer.write_all(&vec![0; (arch_offset - offset) as usize])?;
+                for zeroes in zero_padding((arch_offset - offset) as usize) {
+                    writer.write_all(zeroes).await?;
+                }
                 offset = arch_offset;
             }
-            writer.write_all(&arch.data)?;
+            match &arch.data {
+                SliceData::Stream {
+                    source,
+                    offset,
+                    size,
+                } => write_stream_async(source, *offset, *size, writer).await?,
+                data => {
+                    writer
+                        .write_all(data.as_bytes().unwrap_or_default())
+                        .await?
+                }
+            }
             offset += arch.data.len() as i64;
         }
-        Ok(())
+        writer.flush().await?;
+        Ok(offset as u64)
+    }
+
+    /// Write Mach-O fat binary to a file asynchronously
+    ///
+    /// See [`FatWriter::write_to_file`].
+    #[cfg(feature = "tokio")]
+    pub async fn write_to_file_async<P: AsRef<Path>>(&self, path: P) -> Result<u64, Error> {
+        let path = path.as_ref();
+        if self.options.write_in_place {
+            let file = tokio::fs::File::create(path).await?;
+            return self.write_to_new_file_async(file).await;
+        }
+        let temp_path = temp_path(path);
+        let result = match tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .await
+        {
+            Ok(file) => match self.write_to_new_file_async(file).await {
+                Ok(len) => match tokio::fs::rename(&temp_path, path).await {
+                    Ok(()) => sync_parent_dir_async(path).await.map(|()| len),
+                    Err(err) => Err(err.into()),
+                },
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err.into()),
+        };
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn write_to_new_file_async(&self, file: tokio::fs::File) -> Result<u64, Error> {
+        #[cfg(unix)]
+        file.set_permissions(std::fs::Permissions::from_mode(self.file_mode()))
+            .await?;
+        let mut writer = tokio::io::BufWriter::new(file);
+        let len = self.write_to_async(&mut writer).await?;
+        writer.into_inner().sync_all().await?;
+        Ok(len)
     }
 
     /// Write Mach-O fat binary to a file
-    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
-        let file = File::create(path)?;
+    ///
+    /// The fat binary is written to a temporary file in the same directory,
+    /// synced and then renamed over `path`, so `path` never holds a partially
+    /// written file. See [`FatWriter::set_write_in_place`]. Returns the
+    /// number of bytes written.
+    #[cfg(feature = "std-fs")]
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<u64, Error> {
+        let path = path.as_ref();
+        if self.options.write_in_place {
+            return self.write_to_new_file(File::create(path)?);
+        }
+        self.replace_file(path)
+    }
+
+    /// Write Mach-O fat binary to a temporary file and rename it over `path`
+    ///
+    /// Unlike writing in place this is safe when slices are read from `path`.
+    #[cfg(feature = "std-fs")]
+    pub(crate) fn replace_file(&self, path: &Path) -> Result<u64, Error> {
+        let temp_path = temp_path(path);
+        let result = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .map_err(Error::from)
+            .and_then(|file| self.write_to_new_file(file))
+            .and_then(|len| {
+                fs::rename(&temp_path, path)?;
+                sync_parent_dir(path)?;
+                Ok(len)
+            });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn write_to_new_file(&self, file: File) -> Result<u64, Error> {
         #[cfg(unix)]
         {
             let mut perm = file.metadata()?.permissions();
-            perm.set_mode(0o755);
+            perm.set_mode(self.file_mode());
             file.set_permissions(perm)?;
         }
         let mut writer = BufWriter::new(file);
-        self.write_to(&mut writer)?;
-        Ok(())
+        let len = self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        Ok(len)
     }
 }
 
-fn get_align_from_cpu_types(cpu_type: CpuType, cpu_subtype: CpuSubType) -> i64 {
-    if let Some(arch_name) = get_arch_name_from_types(cpu_type, cpu_subtype) {
-        if let Some((cpu_type, _)) = get_arch_from_flag(arch_name) {
-            match cpu_type {
-                // embedded
-                CPU_TYPE_ARM | CPU_TYPE_ARM64 | CPU_TYPE_ARM64_32 => return 0x4000,
-                // desktop
-                CPU_TYPE_X86_64 | CPU_TYPE_I386 | CPU_TYPE_POWERPC | CPU_TYPE_POWERPC64 => {
-                    return 0x1000
+/// Permission bits of a file, `None` where there are none
+#[cfg(feature = "std-fs")]
+fn source_mode(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    return Some(metadata.permissions().mode() & 0o7777);
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Directory holding `path`
+#[cfg(feature = "std-fs")]
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Make a rename to `path` durable by syncing the directory holding it
+#[cfg(feature = "std-fs")]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(parent_dir(path))?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = parent_dir(path);
+    Ok(())
+}
+
+/// Like [`sync_parent_dir`], without blocking the executor
+#[cfg(feature = "tokio")]
+async fn sync_parent_dir_async(path: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    tokio::fs::File::open(parent_dir(path))
+        .await?
+        .sync_all()
+        .await?;
+    #[cfg(not(unix))]
+    let _ = parent_dir(path);
+    Ok(())
+}
+
+/// A path for a temporary file next to `path`
+#[cfg(feature = "std-fs")]
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let count = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.tmp", name, process::id(), count))
+}
+
+/// Fill `buf` from `reader`, returning how many bytes were read before EOF
+pub(crate) fn read_prefix<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// Read cputype, cpusubtype and filetype from a thin Mach-O header
+pub(crate) fn parse_macho_header(buf: &[u8]) -> Option<(CpuType, CpuSubType, u32)> {
+    if buf.len() < SIZEOF_HEADER_64 {
+        return None;
+    }
+    let (read, _) = macho_field_reader(buf)?;
+    Some((read(buf, 4)?, read(buf, 8)?, read(buf, 12)?))
+}
+
+type FieldReader = fn(&[u8], usize) -> Option<u32>;
+
+/// Get a reader for u32 fields in the byte order of a Mach-O header, and whether it's 64-bit
+fn macho_field_reader(buf: &[u8]) -> Option<(FieldReader, bool)> {
+    fn le(buf: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            buf.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+    fn be(buf: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(
+            buf.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+    match le(buf, 0)? {
+        MH_MAGIC => Some((le, false)),
+        MH_MAGIC_64 => Some((le, true)),
+        MH_CIGAM => Some((be, false)),
+        MH_CIGAM_64 => Some((be, true)),
+        _ => None,
+    }
+}
+
+/// Size of the Mach-O header plus its load commands
+fn macho_load_commands_end(buf: &[u8]) -> Option<usize> {
+    let (read, is_64) = macho_field_reader(buf)?;
+    let header_len = if is_64 {
+        SIZEOF_HEADER_64
+    } else {
+        SIZEOF_HEADER_32
+    };
+    header_len.checked_add(read(buf, 20)? as usize)
+}
+
+/// Compute the alignment of a thin Mach-O binary like cctools lipo does
+///
+/// Object files use their largest section alignment. Other files use the
+/// page size for i386, x86_64, ppc, ppc64 and ARM, and the smallest
+/// alignment of their segment addresses for other cpu types. The page size
+/// of the cpu family is used when the load commands don't tell.
+fn get_align(buf: &[u8], cpu_type: CpuType) -> i64 {
+    let is_object = parse_macho_header(buf).is_some_and(|(_, _, filetype)| filetype == MH_OBJECT);
+    match cpu_type {
+        CPU_TYPE_I386 | CPU_TYPE_X86_64 | CPU_TYPE_POWERPC | CPU_TYPE_POWERPC64 | CPU_TYPE_ARM
+        | CPU_TYPE_ARM64 | CPU_TYPE_ARM64_32
+            if !is_object =>
+        {
+            get_align_from_cpu_type(cpu_type)
+        }
+        _ => match get_align_from_load_commands(buf) {
+            Some(bits) => 1 << bits,
+            None => get_align_from_cpu_type(cpu_type),
+        },
+    }
+}
+
+fn get_align_from_load_commands(buf: &[u8]) -> Option<u32> {
+    let (read, is_64) = macho_field_reader(buf)?;
+    let read64 = |offset: usize| -> Option<u64> {
+        let (hi, lo) = match read(buf, 0)? {
+            MH_MAGIC | MH_MAGIC_64 => (read(buf, offset + 4)?, read(buf, offset)?),
+            _ => (read(buf, offset)?, read(buf, offset + 4)?),
+        };
+        Some((hi as u64) << 32 | lo as u64)
+    };
+    let filetype = read(buf, 12)?;
+    let ncmds = read(buf, 16)?;
+    let mut offset = if is_64 {
+        SIZEOF_HEADER_64
+    } else {
+        SIZEOF_HEADER_32
+    };
+    let mut align: Option<u32> = None;
+    for _ in 0..ncmds {
+        let cmd = read(buf, offset)?;
+        let cmdsize = read(buf, offset + 4)? as usize;
+        if cmdsize == 0 {
+            return None;
+        }
+        // (vmaddr, nsects, first section, section size, offset of section align)
+        let segment = match cmd {
+            LC_SEGMENT => Some((
+                read(buf, offset + 24)? as u64,
+                read(buf, offset + 48)?,
+                56,
+                68,
+                44,
+            )),
+            LC_SEGMENT_64 => Some((read64(offset + 24)?, read(buf, offset + 64)?, 72, 80, 52)),
+            _ => None,
+        };
+        if let Some((vmaddr, nsects, sections, section_size, align_offset)) = segment {
+            if filetype == MH_OBJECT {
+                for index in 0..nsects as usize {
+                    let section = offset + sections + index * section_size;
+                    let section_align = read(buf, section + align_offset)?;
+                    align = Some(align.map_or(section_align, |align| align.max(section_align)));
                 }
-                CPU_TYPE_MC680X0 | CPU_TYPE_MC88000 | CPU_TYPE_SPARC | CPU_TYPE_I860
-                | CPU_TYPE_HPPA => return 0x2000,
-                _ => {}
+            } else {
+                let segment_align = guess_align(vmaddr);
+                align = Some(align.map_or(segment_align, |align| align.min(segment_align)));
             }
         }
+        offset = offset.checked_add(cmdsize)?;
+    }
+    // Never emit an alignment of 0
+    align.map(|align| align.clamp(2, MAXSECTALIGN))
+}
+
+/// Largest power of 2 dividing a segment address, like cctools `guess_align`
+fn guess_align(vmaddr: u64) -> u32 {
+    if vmaddr == 0 {
+        return MAXSECTALIGN;
+    }
+    vmaddr.trailing_zeros().clamp(2, MAXSECTALIGN)
+}
+
+fn get_align_from_cpu_type(cpu_type: CpuType) -> i64 {
+    match cpu_type {
+        // embedded
+        CPU_TYPE_ARM | CPU_TYPE_ARM64 | CPU_TYPE_ARM64_32 => 0x4000,
+        // desktop
+        CPU_TYPE_X86_64 | CPU_TYPE_I386 | CPU_TYPE_POWERPC | CPU_TYPE_POWERPC64 => 0x1000,
+        CPU_TYPE_MC680X0 | CPU_TYPE_MC88000 | CPU_TYPE_SPARC | CPU_TYPE_I860 | CPU_TYPE_HPPA => {
+            0x2000
+        }
+        _ => 4,
     }
-    0
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs;
+    use std::{borrow::Cow, fs};
 
-    use super::FatWriter;
+    use goblin::mach::cputype::{
+        CPU_SUBTYPE_ARM_V7, CPU_SUBTYPE_ARM_V7S, CPU_TYPE_ARM, CPU_TYPE_X86_64,
+    };
+
+    use super::{
+        DuplicatePolicy, FatFormat, FatWriter, FatWriterOptions, SliceData, SortOrder,
+        COPY_BUFFER_SIZE,
+    };
+    use crate::error::Error;
     use crate::read::FatReader;
 
     #[test]
@@ -413,6 +2023,7 @@ mod tests {
         let reader = FatReader::new(&out);
         assert!(reader.is_ok());
 
+        #[cfg(feature = "std-fs")]
         fat.write_to_file("tests/output/fat").unwrap();
     }
 
@@ -446,6 +2057,7 @@ mod tests {
         let reader = FatReader::new(&out);
         assert!(reader.is_ok());
 
+        #[cfg(feature = "std-fs")]
         fat.write_to_file("tests/output/fat.a").unwrap();
     }
 
@@ -463,6 +2075,7 @@ mod tests {
         let reader = FatReader::new(&out);
         assert!(reader.is_ok());
 
+        #[cfg(feature = "std-fs")]
         fat.write_to_file("tests/output/fat_bc").unwrap();
     }
 
@@ -477,5 +2090,950 @@ mod tests {
         assert!(arm64.is_some());
         assert!(fat.exists("x86_64"));
         assert!(!fat.exists("arm64"));
+
+        // The remaining slices no longer pad to the removed slice's alignment
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64.a").unwrap())
+            .unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        fat.remove("arm64").unwrap();
+        let mut after = Vec::new();
+        fat.write_to(&mut after).unwrap();
+        assert_eq!(after, out);
+    }
+
+    #[test]
+    fn test_fat_writer_write_to_short_writes() {
+        use std::io::{self, IoSlice, Write};
+
+        /// Accepts at most 1000 bytes per call, from several buffers at once
+        struct Trickle(Vec<u8>);
+
+        impl Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.write_vectored(&[IoSlice::new(buf)])
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                let mut len = 0;
+                for buf in bufs {
+                    let n = buf.len().min(1000 - len);
+                    self.0.extend_from_slice(&buf[..n]);
+                    len += n;
+                }
+                Ok(len)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let mut fat =
+            FatWriter::with_options(FatWriterOptions::new().sort_order(SortOrder::LipoCompatible));
+        fat.add(buf.clone()).unwrap();
+        let mut out = Trickle(Vec::new());
+        assert_eq!(fat.write_to(&mut out).unwrap(), buf.len() as u64);
+        assert_eq!(out.0, buf);
+
+        assert_eq!(
+            super::zero_padding(super::ZEROES.len() * 2 + 3)
+                .map(<[u8]>::len)
+                .collect::<Vec<_>>(),
+            [super::ZEROES.len(), super::ZEROES.len(), 3]
+        );
+    }
+
+    #[test]
+    fn test_fat_writer_remove_family() {
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        fat.add_with_arch(vec![0xaa; 100], CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7, 14)
+            .unwrap();
+        fat.add_with_arch(vec![0xbb; 100], CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S, 14)
+            .unwrap();
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let arm: Vec<_> = reader
+            .extract_family("arm")
+            .iter()
+            .map(|arch| (arch.arch_name, arch.slice()))
+            .collect();
+        assert_eq!(
+            arm,
+            [
+                (Some("armv7"), &[0xaa; 100][..]),
+                (Some("armv7s"), &[0xbb; 100][..])
+            ]
+        );
+        assert_eq!(reader.extract_family("armv7s").len(), 1);
+        assert!(reader.extract_family("i386").is_empty());
+
+        assert_eq!(fat.remove_family("arm"), ["armv7", "armv7s"]);
+        assert!(fat.remove_family("arm").is_empty());
+        assert_eq!(fat.archs(), ["x86_64", "arm64"]);
+    }
+
+    #[test]
+    fn test_fat_writer_header_gap_bytes_round_trip() {
+        let buf = fs::read("tests/fixtures/simplefat_padded").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let mut fat = FatWriter::new();
+        fat.add(buf.clone()).unwrap();
+        fat.set_header_gap_bytes(reader.header_gap_bytes());
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(out, buf);
+    }
+
+    #[test]
+    fn test_fat_writer_sort_by_size() {
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        assert!(f1.len() < f2.len());
+        for (order, expected) in [
+            (SortOrder::BySizeAsc, [f1.len(), f2.len()]),
+            (SortOrder::BySizeDesc, [f2.len(), f1.len()]),
+        ] {
+            let mut fat = FatWriter::new();
+            fat.add(f2.clone()).unwrap();
+            fat.add(f1.clone()).unwrap();
+            fat.set_sort_order(order);
+            let mut out = Vec::new();
+            fat.write_to(&mut out).unwrap();
+
+            let reader = FatReader::new(&out).unwrap();
+            let sizes: Vec<usize> = reader.arches().map(|arch| arch.size as usize).collect();
+            assert_eq!(sizes, expected);
+        }
+    }
+
+    #[test]
+    fn test_fat_writer_preserve_fat_align() {
+        let buf = fs::read("tests/fixtures/simplefat_align12").unwrap();
+        let mut fat = FatWriter::new();
+        fat.add(buf.clone()).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(out, buf);
+
+        let reader = FatReader::new(&out).unwrap();
+        let aligns: Vec<u32> = reader.arches().map(|arch| arch.align).collect();
+        assert_eq!(aligns, [12, 14]);
+    }
+
+    #[test]
+    fn test_fat_writer_add_borrowed() {
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("tests/fixtures/simplefat").unwrap();
+        let mut fat = FatWriter::new();
+        fat.add_borrowed(&f1).unwrap();
+        assert!(fat.add_borrowed(&f2).is_err());
+        fat.remove("x86_64");
+        fat.add_borrowed(&f2).unwrap();
+        assert!(fat
+            .arches
+            .iter()
+            .all(|arch| matches!(arch.data, SliceData::Memory(Cow::Borrowed(_)))));
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(out, f2);
+    }
+
+    #[test]
+    fn test_fat_writer_set_declared_offset() {
+        let mut fat = FatWriter::new();
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        fat.add(f1.clone()).unwrap();
+        fat.add(f2).unwrap();
+        assert!(fat.set_declared_offset("x86_64", 0x1234));
+        assert!(!fat.set_declared_offset("i386", 0x1234));
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+
+        let reader = FatReader::new(&out).unwrap();
+        let arch = reader
+            .arches()
+            .find(|arch| arch.cputype == CPU_TYPE_X86_64)
+            .unwrap();
+        assert_eq!(arch.offset, 0x1234);
+        // The slice itself is still written at the computed offset
+        assert_eq!(&out[0x1000..0x1000 + f1.len()], &f1[..]);
+    }
+
+    #[cfg(all(unix, feature = "std-fs"))]
+    #[test]
+    fn test_fat_writer_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/simplefat").unwrap())
+            .unwrap();
+        fat.write_to_file("tests/output/fat_mode_exe").unwrap();
+        assert_eq!(mode("tests/output/fat_mode_exe"), 0o755);
+
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/simplefat.dylib").unwrap())
+            .unwrap();
+        fat.write_to_file("tests/output/fat_mode_dylib").unwrap();
+        assert_eq!(mode("tests/output/fat_mode_dylib"), 0o644);
+
+        fat.set_file_mode(0o600);
+        fat.write_to_file("tests/output/fat_mode_dylib").unwrap();
+        assert_eq!(mode("tests/output/fat_mode_dylib"), 0o600);
+
+        // Slices added from a path mirror the source file
+        let source = "tests/output/fat_mode_source.dylib";
+        fs::copy("tests/fixtures/simplefat.dylib", source).unwrap();
+        fs::set_permissions(source, fs::Permissions::from_mode(0o750)).unwrap();
+        let mut fat = FatWriter::new();
+        fat.add_path(source).unwrap();
+        fat.write_to_file("tests/output/fat_mode_dylib").unwrap();
+        assert_eq!(mode("tests/output/fat_mode_dylib"), 0o750);
+
+        // Archives never make the output executable, special bits are dropped
+        let source = "tests/output/fat_mode_source.a";
+        fs::copy("tests/fixtures/thin_x86_64.a", source).unwrap();
+        fs::set_permissions(source, fs::Permissions::from_mode(0o4755)).unwrap();
+        let mut fat = FatWriter::new();
+        fat.add_path(source).unwrap();
+        fat.write_to_file("tests/output/fat_mode_archive").unwrap();
+        let full_mode = fs::metadata("tests/output/fat_mode_archive")
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(full_mode & 0o7777, 0o644);
+    }
+
+    #[test]
+    fn test_fat_writer_preserve_input_order() {
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        // arm64 is larger, so it comes first
+        fat.set_sort_order(SortOrder::BySizeDesc);
+        assert_eq!(fat.archs(), ["arm64", "x86_64"]);
+        let mut input = Vec::new();
+        fat.write_to(&mut input).unwrap();
+
+        let cpu_types = |buf: &[u8]| -> Vec<u32> {
+            let reader = FatReader::new(buf).unwrap();
+            let cpu_types = reader.arches().map(|arch| arch.cputype).collect();
+            cpu_types
+        };
+
+        let mut fat = FatWriter::new();
+        fat.add(input.clone()).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_ne!(cpu_types(&out), cpu_types(&input));
+
+        fat.preserve_input_order(true);
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(cpu_types(&out), cpu_types(&input));
+        assert_eq!(out, input);
+
+        // Thin inputs are still sorted, x86_64h lands between the fat slices
+        let hellofat = fs::read("tests/fixtures/hellofat").unwrap();
+        let x86_64h = FatReader::new(&hellofat)
+            .unwrap()
+            .extract("x86_64h")
+            .unwrap();
+        fat.add(x86_64h).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(
+            FatReader::new(&out).unwrap().archs(),
+            ["arm64", "x86_64h", "x86_64"]
+        );
+    }
+
+    #[test]
+    fn test_fat_writer_lipo_compatible_order() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let mut fat =
+            FatWriter::with_options(FatWriterOptions::new().sort_order(SortOrder::LipoCompatible));
+        for arch in ["arm64", "x86_64h", "x86_64"] {
+            fat.add(reader.extract(arch).unwrap()).unwrap();
+        }
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        // Each slice is aligned to its own cpu type's page size, like lipo does
+        let layout: Vec<_> = FatReader::new(&out)
+            .unwrap()
+            .arches()
+            .map(|arch| (arch.arch_name.unwrap(), arch.offset, arch.align))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                ("x86_64", 0x1000, 12),
+                ("x86_64h", 0xe000, 12),
+                ("arm64", 0x1c000, 14)
+            ]
+        );
+
+        // lipo compares cpusubtypes as signed integers
+        let mut arm64e = reader.extract("arm64").unwrap().to_vec();
+        arm64e[8..12].copy_from_slice(&0x8000_0002u32.to_le_bytes());
+        fat.add(arm64e).unwrap();
+        assert_eq!(fat.archs(), ["x86_64", "x86_64h", "arm64e", "arm64"]);
+        fat.set_sort_order(SortOrder::Alignment);
+        assert_eq!(fat.archs(), ["x86_64", "x86_64h", "arm64", "arm64e"]);
+
+        fat.set_sort_order(SortOrder::Custom(|a, b| b.arch_name.cmp(&a.arch_name)));
+        assert_eq!(fat.archs(), ["x86_64h", "x86_64", "arm64e", "arm64"]);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_fat_writer_add_path() {
+        let mut fat = FatWriter::new();
+        fat.add_path("tests/fixtures/simplefat").unwrap();
+        assert!(fat
+            .arches
+            .iter()
+            .all(|arch| matches!(arch.data, SliceData::Stream { .. })));
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(out, fs::read("tests/fixtures/simplefat").unwrap());
+
+        let mut streamed = FatWriter::new();
+        streamed.add_path("tests/fixtures/thin_x86_64").unwrap();
+        streamed.add_path("tests/fixtures/thin_arm64.a").unwrap();
+        let mut in_memory = FatWriter::new();
+        in_memory
+            .add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        in_memory
+            .add(fs::read("tests/fixtures/thin_arm64.a").unwrap())
+            .unwrap();
+        let mut out1 = Vec::new();
+        streamed.write_to(&mut out1).unwrap();
+        let mut out2 = Vec::new();
+        in_memory.write_to(&mut out2).unwrap();
+        assert_eq!(out1, out2);
+
+        assert!(streamed.add_path("tests/fixtures/thin_x86_64").is_err());
+        let x86_64 = streamed.remove("x86_64").unwrap();
+        assert_eq!(x86_64, fs::read("tests/fixtures/thin_x86_64").unwrap());
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_fat_writer_try_remove_read_error() {
+        let path = "tests/output/try_remove_truncated";
+        fs::copy("tests/fixtures/hellofat", path).unwrap();
+        let mut fat = FatWriter::new();
+        fat.add_path(path).unwrap();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(0x1000)
+            .unwrap();
+        assert!(matches!(fat.try_remove("arm64"), Err(Error::Io(_))));
+        assert!(!fat.exists("arm64"));
+        assert!(fat.remove_by_type(CPU_TYPE_X86_64, 3).is_err());
+        assert_eq!(fat.try_remove("i386").unwrap(), None);
+    }
+
+    /// A thin Mach-O header followed by `len` bytes of zeros, without allocating them
+    struct SparseReader {
+        header: Vec<u8>,
+        len: u64,
+        pos: u64,
+    }
+
+    impl std::io::Read for SparseReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.len.saturating_sub(self.pos) as usize);
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                *byte = *self.header.get(self.pos as usize + i).unwrap_or(&0);
+            }
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl std::io::Seek for SparseReader {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.pos = match pos {
+                std::io::SeekFrom::Start(pos) => pos,
+                std::io::SeekFrom::End(pos) => (self.len as i64 + pos) as u64,
+                std::io::SeekFrom::Current(pos) => (self.pos as i64 + pos) as u64,
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn test_fat_writer_options_format() {
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().format(FatFormat::Fat64));
+        fat.add(f1.clone()).unwrap();
+        fat.add(f2.clone()).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert!(reader.info().is_fat64);
+        assert_eq!(reader.extract("x86_64").unwrap(), &f1[..]);
+        assert_eq!(reader.extract("arm64").unwrap(), &f2[..]);
+
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().format(FatFormat::Fat32));
+        fat.add(f1).unwrap();
+        fat.add_reader(SparseReader {
+            header: f2,
+            len: 1 << 32,
+            pos: 0,
+        })
+        .unwrap();
+        let err = fat.write_to(&mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::Fat32Overflow(arch) if arch == "arm64"));
+    }
+
+    #[test]
+    fn test_fat_writer_large_stream_slice() {
+        // Spans several copy buffers
+        let header = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let len = 3 * COPY_BUFFER_SIZE + 5;
+        let mut fat = FatWriter::new();
+        fat.add_reader(SparseReader {
+            header: header.clone(),
+            len,
+            pos: 0,
+        })
+        .unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let slice = reader.extract("arm64").unwrap();
+        assert_eq!(slice.len() as u64, len);
+        assert_eq!(&slice[..header.len()], &header[..]);
+        assert!(slice[header.len()..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_fat_writer_options_align() {
+        let options = FatWriterOptions::new()
+            .align("x86_64", 12)
+            .align("arm64", 15);
+        let mut fat = FatWriter::with_options(options);
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let layout: Vec<_> = reader
+            .arches()
+            .map(|arch| (arch.offset, arch.align))
+            .collect();
+        assert_eq!(layout, [(0x1000, 12), (0x8000, 15)]);
+
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().align("x86_64", 64));
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        assert!(matches!(
+            fat.write_to(&mut Vec::new()),
+            Err(Error::InvalidMachO(_))
+        ));
+
+        // Capability bits don't keep an override from matching
+        let mut arm64e = fs::read("tests/fixtures/thin_arm64").unwrap();
+        arm64e[8..12].copy_from_slice(&0x8000_0002u32.to_le_bytes());
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().align("arm64e", 15));
+        fat.add(arm64e).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.arches().next().unwrap().align, 15);
+    }
+
+    #[test]
+    fn test_fat_writer_duplicate_policy() {
+        let thin = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let fat = fs::read("tests/fixtures/hellofat").unwrap();
+        let fat_x86_64 = FatReader::new(&fat)
+            .unwrap()
+            .extract("x86_64")
+            .unwrap()
+            .len();
+        assert_ne!(thin.len(), fat_x86_64);
+
+        let mut writer = FatWriter::new();
+        writer.add_borrowed(&thin).unwrap();
+        assert!(matches!(
+            writer.add_borrowed(&fat),
+            Err(Error::DuplicatedArch(_))
+        ));
+
+        // Unknown architectures are named after their cputype and cpusubtype
+        let unknown = crate::test_support::minimal_macho(0x1234, 5);
+        let mut writer = FatWriter::new();
+        writer.add_borrowed(&unknown).unwrap();
+        assert!(matches!(
+            writer.add_borrowed(&unknown),
+            Err(Error::DuplicatedArch(arch)) if arch == "cputype 4660 cpusubtype 5"
+        ));
+
+        let mut writer = FatWriter::new();
+        writer.set_duplicate_policy(DuplicatePolicy::Skip);
+        writer.add_borrowed(&thin).unwrap();
+        writer.add_borrowed(&fat).unwrap();
+        assert!(writer.exists("x86_64h") && writer.exists("arm64"));
+        assert_eq!(writer.remove("x86_64").unwrap().len(), thin.len());
+
+        let options = FatWriterOptions::new().duplicate_policy(DuplicatePolicy::Replace);
+        let mut writer = FatWriter::with_options(options);
+        writer.add_borrowed(&thin).unwrap();
+        writer.add_borrowed(&fat).unwrap();
+        assert_eq!(writer.remove("x86_64").unwrap().len(), fat_x86_64);
+
+        // Archives are checked as well
+        let archive = fs::read("tests/fixtures/thin_x86_64.a").unwrap();
+        let mut writer = FatWriter::new();
+        writer.add_borrowed(&archive).unwrap();
+        assert!(writer.add_borrowed(&archive).is_err());
+    }
+
+    #[test]
+    fn test_fat_writer_replace() {
+        let thin = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let fat = fs::read("tests/fixtures/hellofat").unwrap();
+        let old_x86_64 = FatReader::new(&fat).unwrap().extract("x86_64").unwrap();
+
+        let mut writer = FatWriter::new();
+        writer.add_borrowed(&fat).unwrap();
+        let old = writer.replace("x86_64", thin.clone()).unwrap();
+        assert_eq!(old.as_deref(), Some(old_x86_64));
+        let mut out = Vec::new();
+        writer.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.extract("x86_64").unwrap(), &thin[..]);
+        assert_eq!(reader.arches().count(), 3);
+
+        assert!(matches!(
+            writer.replace("arm64", thin.clone()),
+            Err(Error::ArchMismatch { expected, found }) if expected == "arm64" && found == "x86_64"
+        ));
+        assert!(writer.replace("i386", thin.clone()).is_err());
+        assert!(writer.replace("x86_64", fat.clone()).is_err());
+
+        let mut writer = FatWriter::new();
+        assert!(writer.replace("x86_64", thin).unwrap().is_none());
+        assert!(writer.exists("x86_64"));
+    }
+
+    #[test]
+    fn test_fat_writer_validate() {
+        use crate::verify::Diagnostic;
+
+        let mut fat = FatWriter::new();
+        assert_eq!(fat.validate().unwrap(), [Diagnostic::NoArches]);
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        assert!(fat.validate().unwrap().is_empty());
+
+        fat.set_declared_offset("arm64", 0x4001);
+        assert_eq!(
+            fat.validate().unwrap(),
+            [
+                Diagnostic::Misaligned {
+                    index: 1,
+                    offset: 0x4001,
+                    align: 14
+                },
+                Diagnostic::Overlap {
+                    first: 0,
+                    second: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fat_writer_check_filetypes() {
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().check_filetypes(true));
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        assert!(fat.write_to(&mut Vec::new()).is_ok());
+
+        let dylib = fs::read("tests/fixtures/simplefat.dylib").unwrap();
+        let arm64 = FatReader::new(&dylib).unwrap().extract("arm64").unwrap();
+        fat.replace("arm64", arm64.to_vec()).unwrap();
+        let err = fat.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "slices have different filetypes: x86_64 (EXECUTE), arm64 (DYLIB)"
+        );
+
+        fat.set_check_filetypes(false);
+        assert!(fat.write_to(&mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_get_align() {
+        use super::get_align;
+        use crate::test_support::minimal_macho;
+        use goblin::mach::cputype::CPU_TYPE_ARM64;
+
+        // Like lipo, x86_64 and ARM executables use their page size
+        let exe = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert_eq!(get_align(&exe, CPU_TYPE_X86_64), 0x1000);
+        let arm64 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        assert_eq!(get_align(&arm64, CPU_TYPE_ARM64), 0x4000);
+
+        // Executables use the smallest segment alignment
+        assert_eq!(get_align(&exe, 0x1234), 0x4000);
+
+        // Object files use the largest section alignment
+        let mut obj = minimal_macho(CPU_TYPE_ARM64, 0);
+        obj[16..20].copy_from_slice(&1u32.to_le_bytes());
+        obj[20..24].copy_from_slice(&(72u32 + 2 * 80).to_le_bytes());
+        let mut segment = vec![0; 72 + 2 * 80];
+        segment[0..4].copy_from_slice(&0x19u32.to_le_bytes());
+        segment[4..8].copy_from_slice(&(72u32 + 2 * 80).to_le_bytes());
+        segment[64..68].copy_from_slice(&2u32.to_le_bytes());
+        segment[72 + 52..72 + 56].copy_from_slice(&4u32.to_le_bytes());
+        segment[72 + 80 + 52..72 + 80 + 56].copy_from_slice(&3u32.to_le_bytes());
+        obj.extend(segment);
+        assert_eq!(get_align(&obj, 0x1234), 16);
+        assert_eq!(get_align(&obj, CPU_TYPE_ARM64), 16);
+
+        // Without load commands fall back to the page size, and never to 0
+        let bare = minimal_macho(CPU_TYPE_ARM64, 0);
+        assert_eq!(get_align(&bare, CPU_TYPE_ARM64), 0x4000);
+        assert_eq!(get_align(&bare, 0x1234), 4);
+    }
+
+    #[test]
+    fn test_fat_writer_add_with_arch() {
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        let custom = vec![0xaa; 100];
+        fat.add_with_arch(custom.clone(), 0x1234, 5, 12).unwrap();
+        assert!(fat.exists_by_type(0x1234, 5));
+        assert!(!fat.exists_by_type(0x1234, 6));
+        assert!(fat.add_with_arch(vec![0; 4], 0x1234, 6, 16).is_err());
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let arch = reader.arches().find(|arch| arch.cputype == 0x1234).unwrap();
+        assert_eq!((arch.cputype, arch.cpusubtype, arch.align), (0x1234, 5, 12));
+        assert_eq!(arch.slice(), &custom[..]);
+        assert!(reader
+            .archs()
+            .contains(&"cputype 4660 cpusubtype 5".to_string()));
+        assert_eq!(fat.archs(), reader.archs());
+
+        assert_eq!(fat.remove_by_type(0x1234, 5).unwrap(), Some(custom));
+        assert!(!fat.exists_by_type(0x1234, 5));
+        assert!(fat.exists("x86_64"));
+    }
+
+    /// Build a BSD-style ar archive without a symbol table
+    fn make_archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut ar = b"!<arch>\n".to_vec();
+        for (name, data) in members {
+            let header = format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                name,
+                0,
+                0,
+                0,
+                644,
+                data.len()
+            );
+            ar.extend(header.as_bytes());
+            ar.extend(*data);
+            if data.len() % 2 == 1 {
+                ar.push(b'\n');
+            }
+        }
+        ar
+    }
+
+    #[test]
+    fn test_fat_writer_archive_member_arches() {
+        use crate::test_support::minimal_macho;
+        use goblin::mach::cputype::CPU_TYPE_ARM64;
+
+        let x86_64 = minimal_macho(CPU_TYPE_X86_64, 3);
+        let arm64 = minimal_macho(CPU_TYPE_ARM64, 0);
+
+        let mut fat = FatWriter::new();
+        let ar = make_archive(&[("a.o", &x86_64), ("b.o", &x86_64)]);
+        fat.add(ar).unwrap();
+        assert!(fat.exists("x86_64"));
+
+        // Capability bits such as CPU_SUBTYPE_LIB64 don't make members differ
+        let lib64 = minimal_macho(CPU_TYPE_X86_64, 0x8000_0003);
+        let mut fat = FatWriter::new();
+        fat.add(make_archive(&[("a.o", &x86_64), ("b.o", &lib64)]))
+            .unwrap();
+        assert!(fat.exists("x86_64"));
+
+        let ar = make_archive(&[("a.o", &arm64), ("b.o", &x86_64)]);
+        let err = fat.add(ar).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "archive member b.o is x86_64 but a.o is arm64"
+        );
+    }
+
+    #[test]
+    fn test_fat_writer_hide_arm64() {
+        use goblin::mach::cputype::CPU_TYPE_ARM;
+
+        use crate::test_support::minimal_macho;
+
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().hide_arm64(true));
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        assert!(fat.write_to(&mut Vec::new()).is_err());
+
+        fat.add(minimal_macho(CPU_TYPE_ARM, 9)).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(&out[4..8], &1u32.to_be_bytes());
+        let reader = FatReader::new(&out).unwrap();
+        let arches: Vec<_> = reader
+            .arches()
+            .map(|arch| (arch.arch_name, arch.hidden))
+            .collect();
+        assert_eq!(arches, [(Some("armv7"), false), (Some("arm64"), true)]);
+        assert!(reader.verify().is_empty());
+        assert!(reader.extract("arm64").is_some());
+
+        #[cfg(feature = "std-fs")]
+        {
+            // Hidden entries are found when streaming from a file too
+            let path = "tests/output/fat_hidden_arm64";
+            fs::write(path, &out).unwrap();
+            let stream = crate::FatStreamReader::new(fs::File::open(path).unwrap()).unwrap();
+            assert_eq!(stream.arch_names().collect::<Vec<_>>(), ["armv7", "arm64"]);
+            let mut fat = FatWriter::new();
+            fat.add_path(path).unwrap();
+            assert_eq!(fat.archs(), ["armv7", "arm64"]);
+        }
+
+        let mut fat = FatWriter::new();
+        fat.add(out).unwrap();
+        assert!(fat.exists("arm64"));
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_fat_writer_write_to_file_atomic() {
+        let dir = "tests/output/atomic";
+        let path = "tests/output/atomic/fat";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().format(FatFormat::Fat32));
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.write_to_file(path).unwrap();
+        let written = fs::read(path).unwrap();
+        assert!(FatReader::new(&written).is_ok());
+
+        // A failed write leaves the previous file untouched
+        let f2 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        fat.add_reader(SparseReader {
+            header: f2,
+            len: 1 << 32,
+            pos: 0,
+        })
+        .unwrap();
+        assert!(matches!(
+            fat.write_to_file(path),
+            Err(Error::Fat32Overflow(_))
+        ));
+        assert_eq!(fs::read(path).unwrap(), written);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+
+        let mut fat = FatWriter::with_options(FatWriterOptions::new().write_in_place(true));
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.write_to_file(path).unwrap();
+        assert_eq!(fs::read(path).unwrap(), written);
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_fat_writer_digests() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let mut fat = FatWriter::new();
+        fat.preserve_input_order(true);
+        fat.add(buf.clone()).unwrap();
+        let digests = fat.digests().unwrap();
+        assert_eq!(digests, FatReader::new(&buf).unwrap().slice_digests());
+
+        fat.remove("x86_64h").unwrap();
+        let changed = fat.digests().unwrap();
+        assert_ne!(changed.fat, digests.fat);
+        assert_eq!(changed.slices[1], digests.slices[2]);
+    }
+
+    #[test]
+    fn test_fat_writer_code_signature() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let signed = |fat: &FatWriter| -> Vec<bool> {
+            let mut out = Vec::new();
+            fat.write_to(&mut out).unwrap();
+            let reader = FatReader::new(&out).unwrap();
+            let signed = reader
+                .arches()
+                .map(|arch| arch.has_code_signature)
+                .collect();
+            signed
+        };
+
+        let mut fat = FatWriter::new();
+        fat.preserve_input_order(true);
+        fat.set_strip_code_signature(true);
+        fat.add(buf.clone()).unwrap();
+        assert_eq!(signed(&fat), [false, false, false]);
+        let arm64 = FatReader::new(&buf).unwrap().extract("arm64").unwrap();
+        fat.replace("arm64", arm64).unwrap();
+        assert_eq!(signed(&fat), [false, false, false]);
+
+        #[cfg(feature = "codesign")]
+        {
+            let mut fat =
+                FatWriter::with_options(FatWriterOptions::new().ad_hoc_sign("hello.arm64"));
+            fat.preserve_input_order(true);
+            fat.add(buf.clone()).unwrap();
+            assert_eq!(signed(&fat), [true, true, true]);
+            assert_eq!(fat.remove("arm64").unwrap(), arm64);
+        }
+    }
+
+    #[test]
+    fn test_fat_writer_arm64e_capabilities() {
+        let with_subtype = |subtype: u32| {
+            let mut buf = fs::read("tests/fixtures/thin_arm64").unwrap();
+            buf[8..12].copy_from_slice(&subtype.to_le_bytes());
+            buf
+        };
+        let mut fat = FatWriter::new();
+        fat.add(with_subtype(0x8000_0002)).unwrap();
+        assert!(fat.exists("arm64e"));
+        assert!(matches!(
+            fat.add(with_subtype(0x8100_0002)),
+            Err(Error::DuplicatedArch(arch)) if arch == "arm64e"
+        ));
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let arch = reader.arches().next().unwrap();
+        assert_eq!(arch.arch_name, Some("arm64e"));
+        assert_eq!(arch.capabilities, 0x8000_0000);
+        assert!(reader.extract("arm64e").is_some());
+
+        assert!(fat.remove("arm64e").is_some());
+        assert!(!fat.exists("arm64e"));
+    }
+
+    #[test]
+    fn test_fat_writer_write_to_len() {
+        let mut fat = FatWriter::new();
+        assert!(matches!(
+            fat.write_to(&mut Vec::new()),
+            Err(Error::NoArches)
+        ));
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        let mut out = Vec::new();
+        let len = fat.write_to(&mut out).unwrap();
+        assert_eq!(len, out.len() as u64);
+        #[cfg(feature = "std-fs")]
+        assert_eq!(fat.write_to_file("tests/output/fat_len").unwrap(), len);
+    }
+
+    #[test]
+    fn test_fat_writer_add_shares_fat_input() {
+        use std::sync::Arc;
+
+        let f1 = fs::read("tests/fixtures/hellofat").unwrap();
+        let mut fat = FatWriter::new();
+        fat.add(f1.clone()).unwrap();
+        assert!(fat
+            .arches
+            .iter()
+            .all(|arch| matches!(arch.data, SliceData::Shared { .. })));
+
+        let shared = Arc::new(f1.clone());
+        let mut fat = FatWriter::new();
+        fat.preserve_input_order(true);
+        fat.add_shared(shared.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&shared), 4);
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(out, f1);
+
+        assert_eq!(
+            fat.remove("x86_64").unwrap(),
+            FatReader::new(&f1).unwrap().extract("x86_64").unwrap()
+        );
+        assert_eq!(Arc::strong_count(&shared), 3);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_fat_writer_async() {
+        let mut fat = FatWriter::new();
+        fat.add_path_async("tests/fixtures/thin_x86_64")
+            .await
+            .unwrap();
+        fat.add_path("tests/fixtures/thin_arm64").unwrap();
+        // Slices are streamed from the files rather than read into memory
+        assert!(fat
+            .arches
+            .iter()
+            .all(|arch| matches!(arch.data, SliceData::Stream { .. })));
+        let mut expected = Vec::new();
+        fat.write_to(&mut expected).unwrap();
+
+        let mut out = Vec::new();
+        let len = fat.write_to_async(&mut out).await.unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(len, out.len() as u64);
+
+        let len = fat
+            .write_to_file_async("tests/output/fat_async")
+            .await
+            .unwrap();
+        assert_eq!(len, out.len() as u64);
+        assert_eq!(fs::read("tests/output/fat_async").unwrap(), expected);
     }
 }
@@ -0,0 +1,141 @@
+#![cfg(feature = "cli")]
+
+use std::{fs, process::Command};
+
+use fat_macho::{FatReader, FatWriter};
+
+fn fat_macho(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_fat-macho"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_cli_create_archs_thin() {
+    let out = fat_macho(&[
+        "create",
+        "tests/fixtures/thin_x86_64",
+        "tests/fixtures/thin_arm64",
+        "-output",
+        "tests/output/cli_fat",
+    ]);
+    assert!(out.status.success());
+
+    let out = fat_macho(&["archs", "tests/output/cli_fat"]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "x86_64 arm64\n");
+
+    let out = fat_macho(&[
+        "thin",
+        "tests/output/cli_fat",
+        "arm64",
+        "-output",
+        "tests/output/cli_thin_arm64",
+    ]);
+    assert!(out.status.success());
+    assert_eq!(
+        fs::read("tests/output/cli_thin_arm64").unwrap(),
+        fs::read("tests/fixtures/thin_arm64").unwrap()
+    );
+
+    assert!(
+        fat_macho(&["verify", "tests/output/cli_fat", "x86_64", "arm64"])
+            .status
+            .success()
+    );
+    assert!(!fat_macho(&["verify", "tests/output/cli_fat", "i386"])
+        .status
+        .success());
+}
+
+#[test]
+fn test_cli_remove_extract() {
+    let out = fat_macho(&[
+        "remove",
+        "tests/fixtures/hellofat",
+        "x86_64h",
+        "-output",
+        "tests/output/cli_removed",
+    ]);
+    assert!(out.status.success());
+    let buf = fs::read("tests/output/cli_removed").unwrap();
+    let reader = FatReader::new(&buf).unwrap();
+    assert_eq!(reader.arches().count(), 2);
+    assert!(reader.covers(&["x86_64", "arm64"]).is_ok());
+
+    let out = fat_macho(&[
+        "extract",
+        "tests/fixtures/hellofat",
+        "arm64",
+        "-output",
+        "tests/output/cli_extracted",
+    ]);
+    assert!(out.status.success());
+    let buf = fs::read("tests/output/cli_extracted").unwrap();
+    let reader = FatReader::new(&buf).unwrap();
+    let names: Vec<_> = reader.arches().map(|arch| arch.arch_name).collect();
+    assert_eq!(names, [Some("arm64")]);
+}
+
+#[test]
+fn test_cli_thin_input() {
+    let out = fat_macho(&[
+        "extract",
+        "tests/fixtures/thin_arm64",
+        "arm64",
+        "-output",
+        "tests/output/cli_extracted_thin",
+    ]);
+    assert!(out.status.success());
+    let buf = fs::read("tests/output/cli_extracted_thin").unwrap();
+    let reader = FatReader::new(&buf).unwrap();
+    assert_eq!(
+        reader.extract("arm64").unwrap(),
+        fs::read("tests/fixtures/thin_arm64").unwrap()
+    );
+
+    assert!(fat_macho(&["verify", "tests/fixtures/thin_arm64", "arm64"])
+        .status
+        .success());
+    assert!(
+        !fat_macho(&["verify", "tests/fixtures/thin_arm64", "x86_64"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_cli_unknown_arch() {
+    // A slice with a cpu type that has no name
+    let mut unknown = fs::read("tests/fixtures/thin_x86_64").unwrap();
+    unknown[4..8].copy_from_slice(&0x1234u32.to_le_bytes());
+    let mut fat = FatWriter::new();
+    fat.add(unknown).unwrap();
+    fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+        .unwrap();
+    fat.write_to_file("tests/output/cli_unknown").unwrap();
+    let buf = fs::read("tests/output/cli_unknown").unwrap();
+    let names = FatReader::new(&buf).unwrap().archs().join(" ");
+    assert!(names.contains("cputype 4660"));
+
+    let out = fat_macho(&["info", "tests/output/cli_unknown"]);
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!(
+            "Architectures in the fat file: tests/output/cli_unknown are: {}\n",
+            names
+        )
+    );
+
+    let out = fat_macho(&[
+        "extract",
+        "tests/output/cli_unknown",
+        "arm64",
+        "-output",
+        "tests/output/cli_unknown_extracted",
+    ]);
+    assert!(out.status.success());
+    let buf = fs::read("tests/output/cli_unknown_extracted").unwrap();
+    let reader = FatReader::new(&buf).unwrap();
+    assert_eq!(reader.archs(), ["arm64"]);
+}